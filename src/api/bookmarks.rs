@@ -0,0 +1,83 @@
+use cli_table::{format::Justify, Table};
+use serde::Deserialize;
+
+/// One bookmark folder from `v1/bookmarks`.
+#[derive(Debug, Deserialize, Table)]
+pub struct BookmarkFolder {
+    #[table(title = "ID", justify = "Justify::Right")]
+    pub id: u64,
+    #[table(title = "Folder")]
+    pub title: String,
+    #[serde(default)]
+    #[table(title = "Items", justify = "Justify::Right")]
+    pub count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookmarkFoldersResult {
+    pub items: Vec<BookmarkFolder>,
+}
+
+/// One bookmarked item from `v1/bookmarks/{folder_id}`. Only the fields the
+/// listing shows are modelled; a download fetches the full item by id anyway.
+#[derive(Debug, Deserialize, Table)]
+pub struct BookmarkItem {
+    #[table(title = "ID", justify = "Justify::Right")]
+    pub id: u64,
+    #[table(title = "Title")]
+    pub title: String,
+    #[serde(rename = "type")]
+    #[table(title = "Type")]
+    pub kind: String,
+    #[serde(default)]
+    #[table(title = "Year", justify = "Justify::Right")]
+    pub year: u16,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookmarkItemsResult {
+    pub items: Vec<BookmarkItem>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BookmarkFoldersResult, BookmarkItemsResult};
+
+    #[test]
+    fn deserializes_a_folder_list_response() {
+        let result: BookmarkFoldersResult = serde_json::from_str(
+            r#"{
+                "status": 200,
+                "items": [
+                    {"id": 7, "title": "Watch later", "views": 0, "count": 12},
+                    {"id": 9, "title": "Кино"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].title, "Watch later");
+        assert_eq!(result.items[0].count, 12);
+        assert_eq!(result.items[1].count, 0);
+    }
+
+    #[test]
+    fn deserializes_a_folder_contents_response() {
+        let result: BookmarkItemsResult = serde_json::from_str(
+            r#"{
+                "status": 200,
+                "items": [
+                    {"id": 12345, "title": "Дюна / Dune", "type": "movie", "year": 2021},
+                    {"id": 678, "title": "The Series", "type": "serial"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].kind, "movie");
+        assert_eq!(result.items[0].year, 2021);
+        assert_eq!(result.items[1].id, 678);
+    }
+}
@@ -1,17 +1,21 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
 use chrono::serde::ts_seconds::deserialize as from_ts;
 use chrono::{DateTime, Utc};
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use reqwest::Client;
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_struct_wrapper::deserialize_with_root;
+use thiserror::Error;
 
 use crate::utils::StringExt;
 
+pub mod bookmarks;
 pub mod search;
+pub mod watching;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -19,12 +23,154 @@ pub struct Config {
     pub client_id: String,
     pub client_secret: String,
     pub threads: u64,
+    pub max_retries: u64,
+    pub base_delay: Duration,
+    pub proxy: Option<String>,
+    pub no_proxy: bool,
+    pub ip_family: Option<IpFamily>,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    pub request_timeout: Duration,
+    pub qr: bool,
+    pub user_agent: String,
+}
+
+/// Constrains outbound connections to one address family, for dual-stack
+/// networks whose IPv6 route to the CDN is slow or blocked. `None` keeps the
+/// system's own behavior.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    /// The unspecified local address the client binds to; the OS then only
+    /// routes connections of the matching family.
+    pub fn local_address(self) -> std::net::IpAddr {
+        match self {
+            IpFamily::V4 => std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            IpFamily::V6 => std::net::IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED),
+        }
+    }
 }
 
 impl Config {
     pub fn set_threads_count(&mut self, threads: u64) {
         self.threads = threads;
     }
+
+    pub fn set_qr(&mut self, qr: bool) {
+        self.qr = qr;
+    }
+
+    pub fn set_ip_family(&mut self, ip_family: Option<IpFamily>) {
+        self.ip_family = ip_family;
+    }
+
+    /// Connection pool tuning; `None` keeps the default for that knob.
+    pub fn set_pool_tuning(
+        &mut self,
+        max_idle_per_host: Option<usize>,
+        idle_timeout_secs: Option<u64>,
+    ) {
+        if let Some(max_idle) = max_idle_per_host {
+            self.pool_max_idle_per_host = max_idle;
+        }
+        if let Some(secs) = idle_timeout_secs {
+            self.pool_idle_timeout = Duration::from_secs(secs);
+        }
+    }
+
+    /// Applies API endpoint/credential overrides, for mirror domains and for
+    /// testing against a mock server; `None` keeps the default.
+    pub fn set_api_overrides(
+        &mut self,
+        api_url: Option<String>,
+        client_id: Option<String>,
+        client_secret: Option<String>,
+    ) {
+        if let Some(api_url) = api_url {
+            self.api_url = api_url;
+        }
+        if let Some(client_id) = client_id {
+            self.client_id = client_id;
+        }
+        if let Some(client_secret) = client_secret {
+            self.client_secret = client_secret;
+        }
+    }
+
+    /// Proxy URL to route all HTTP traffic through: an explicit flag wins,
+    /// otherwise the conventional environment variables apply.
+    pub fn set_proxy(&mut self, proxy: Option<String>) {
+        self.proxy = proxy
+            .or_else(|| std::env::var("HTTPS_PROXY").ok())
+            .or_else(|| std::env::var("HTTP_PROXY").ok())
+            .or_else(|| std::env::var("ALL_PROXY").ok());
+    }
+
+    /// Forces direct connections, ignoring both the configured proxy and the
+    /// environment variables.
+    pub fn set_no_proxy(&mut self, no_proxy: bool) {
+        self.no_proxy = no_proxy;
+    }
+
+    /// HTTP client shared by the API and the downloader, configured with the
+    /// user agent and with the proxy when one is set.
+    pub fn http_client(&self) -> Result<Client> {
+        let mut builder = Client::builder()
+            .timeout(self.request_timeout)
+            .user_agent(self.user_agent.clone());
+
+        if self.no_proxy {
+            builder = builder.no_proxy();
+        } else if let Some(proxy) = &self.proxy {
+            // Validated here so a malformed URL fails at startup, like
+            // `Proxy::all` used to.
+            let proxy_url: reqwest::Url = proxy.parse()?;
+            let exclusions = std::env::var("NO_PROXY")
+                .or_else(|_| std::env::var("no_proxy"))
+                .unwrap_or_default();
+
+            builder = builder.proxy(reqwest::Proxy::custom(move |url| {
+                match url.host_str() {
+                    Some(host) if host_bypasses_proxy(host, &exclusions) => None,
+                    _ => Some(proxy_url.clone()),
+                }
+            }));
+        }
+
+        if let Some(family) = self.ip_family {
+            builder = builder.local_address(family.local_address());
+        }
+
+        builder = builder
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout);
+
+        builder.build().map_err(Into::into)
+    }
+}
+
+/// Whether a `NO_PROXY`-style exclusion list (comma-separated hosts,
+/// domains, or `*`) covers the host. Domain entries match subdomains too,
+/// with or without a leading dot, mirroring the conventional semantics.
+fn host_bypasses_proxy(host: &str, no_proxy: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+
+    no_proxy
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+
+            let domain = entry.trim_start_matches('.').to_ascii_lowercase();
+            host == domain || host.ends_with(&format!(".{}", domain))
+        })
 }
 
 impl Default for Config {
@@ -34,15 +180,27 @@ impl Default for Config {
             client_secret: "rcaqh7wodackn9ll1uggvqkx2iib6umh".to_string(),
             api_url: "https://api.service-kp.com/".to_string(),
             threads: 4,
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            proxy: None,
+            no_proxy: false,
+            ip_family: None,
+            // Chunked downloads cycle through many short-lived connections to
+            // the same host; keeping a handful warm between chunks avoids
+            // re-handshaking without pinning sockets forever.
+            pool_max_idle_per_host: 8,
+            pool_idle_timeout: Duration::from_secs(90),
+            request_timeout: Duration::from_secs(30),
+            qr: false,
+            user_agent: format!("kinopub-downloader/{}", env!("CARGO_PKG_VERSION")),
         }
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct UserSubscription {
-    pub days: f32,
-    #[serde(deserialize_with = "from_ts")]
-    pub end_time: DateTime<Utc>,
+    // days: f32,
+    // end_time: DateTime<Utc>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -55,49 +213,62 @@ pub struct User {
 }
 deserialize_with_root!("user": User);
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MovieFile {
     pub quality: String,
-    pub codec: String,
+    #[serde(default)]
+    pub codec: Option<String>,
+    #[serde(default)]
+    pub audio: Option<String>,
+    /// ISO language code of the audio, when the API reports one per file.
+    #[serde(default)]
+    pub lang: Option<String>,
     pub url: MovieUrl,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subtitle {
+    pub lang: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MovieUrl {
     pub http: String,
 }
 
-impl ToString for MovieFile {
-    fn to_string(&self) -> String {
-        self.quality.to_owned()
+impl std::fmt::Display for MovieFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.quality)
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Video {
     pub duration: u64,
     pub files: Vec<MovieFile>,
+    #[serde(default)]
+    pub subtitles: Vec<Subtitle>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(remote = "Self")]
-pub struct Movie {
-    pub title: String,
-    pub year: u16,
-    pub videos: Vec<Video>,
-}
-deserialize_with_root!("item": Movie);
-
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GeneralInfo {
-    pub id: u64,
+    // id: u64,
     pub title: String,
     pub year: u16,
     #[serde(rename = "plot")]
     pub description: String,
+    #[serde(default)]
+    pub posters: Option<Posters>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Poster art URLs as the API reports them; only the largest is used.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Posters {
+    pub big: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Rating {
     #[serde(rename = "kinopoisk_rating")]
     pub kinopoisk: Option<f32>,
@@ -105,89 +276,339 @@ pub struct Rating {
     pub imdb: Option<f32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SeriesEpisode {
-    pub id: u64,
+    // id: u64,
     pub title: String,
     pub number: usize,
     pub files: Vec<MovieFile>,
+    #[serde(default)]
+    pub subtitles: Vec<Subtitle>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SeriesSeason {
-    pub id: u64,
+    // id: u64,
     pub title: String,
     pub number: usize,
     pub episodes: Vec<SeriesEpisode>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(remote = "Self", tag = "type")]
+#[derive(Debug)]
 pub enum Item {
-    #[serde(rename(deserialize = "movie"))]
     Movie {
-        #[serde(flatten)]
         info: GeneralInfo,
-        #[serde(flatten)]
         rating: Rating,
         videos: Vec<Video>,
     },
-    #[serde(rename(deserialize = "serial"))]
     Series {
-        #[serde(flatten)]
         info: GeneralInfo,
-        #[serde(flatten)]
         rating: Rating,
         seasons: Vec<SeriesSeason>,
     },
-    #[serde(rename(deserialize = "docuserial"))]
     DocSeries {
-        #[serde(flatten)]
         info: GeneralInfo,
-        #[serde(flatten)]
         rating: Rating,
         seasons: Vec<SeriesSeason>,
     },
-    #[serde(rename(deserialize = "tvshow"))]
     TvShow {
-        #[serde(flatten)]
         info: GeneralInfo,
-        #[serde(flatten)]
         rating: Rating,
         seasons: Vec<SeriesSeason>,
     },
+    /// Any `type` the API grows that we do not model (concert, 3d, ...).
+    /// When it carries videos it behaves like a movie everywhere.
+    Other {
+        info: GeneralInfo,
+        rating: Rating,
+        videos: Vec<Video>,
+    },
 }
-deserialize_with_root!("item": Item);
 
-impl ToString for Movie {
-    fn to_string(&self) -> String {
-        self.title.to_owned()
+// Hand-rolled instead of an internally tagged derive: serde has no
+// data-carrying fallback variant, and an unknown `type` used to fail the
+// whole command.
+impl<'de> Deserialize<'de> for Item {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        struct Root {
+            item: serde_json::Value,
+        }
+
+        #[derive(Deserialize)]
+        struct MovieLike {
+            #[serde(flatten)]
+            info: GeneralInfo,
+            #[serde(flatten)]
+            rating: Rating,
+            videos: Vec<Video>,
+        }
+
+        #[derive(Deserialize)]
+        struct OtherLike {
+            #[serde(flatten)]
+            info: GeneralInfo,
+            #[serde(flatten)]
+            rating: Rating,
+            #[serde(default)]
+            videos: Vec<Video>,
+        }
+
+        #[derive(Deserialize)]
+        struct SeriesLike {
+            #[serde(flatten)]
+            info: GeneralInfo,
+            #[serde(flatten)]
+            rating: Rating,
+            seasons: Vec<SeriesSeason>,
+        }
+
+        let value = Root::deserialize(deserializer)?.item;
+        let kind = value
+            .get("type")
+            .and_then(|kind| kind.as_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        let series = |value| -> Result<SeriesLike, D::Error> {
+            serde_json::from_value(value).map_err(D::Error::custom)
+        };
+
+        match kind.as_str() {
+            "movie" => {
+                let movie: MovieLike = serde_json::from_value(value).map_err(D::Error::custom)?;
+                Ok(Item::Movie {
+                    info: movie.info,
+                    rating: movie.rating,
+                    videos: movie.videos,
+                })
+            }
+            "serial" => series(value).map(|s| Item::Series {
+                info: s.info,
+                rating: s.rating,
+                seasons: s.seasons,
+            }),
+            "docuserial" => series(value).map(|s| Item::DocSeries {
+                info: s.info,
+                rating: s.rating,
+                seasons: s.seasons,
+            }),
+            "tvshow" => series(value).map(|s| Item::TvShow {
+                info: s.info,
+                rating: s.rating,
+                seasons: s.seasons,
+            }),
+            _ => {
+                let other: OtherLike = serde_json::from_value(value).map_err(D::Error::custom)?;
+                Ok(Item::Other {
+                    info: other.info,
+                    rating: other.rating,
+                    videos: other.videos,
+                })
+            }
+        }
     }
 }
 
+/// Mirrors the wire shape `Deserialize` reads: the payload nested under an
+/// `item` key with a `type` tag, so a saved dump loads back through the same
+/// path. Items parsed through the fallback variant are tagged "other".
+impl Serialize for Item {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::Error;
+
+        fn payload<E: serde::ser::Error>(
+            kind: &str,
+            info: &GeneralInfo,
+            rating: &Rating,
+            content_key: &str,
+            content: serde_json::Value,
+        ) -> Result<serde_json::Value, E> {
+            let mut fields = serde_json::Map::new();
+            fields.insert("type".to_string(), serde_json::Value::String(kind.to_string()));
+
+            for part in [
+                serde_json::to_value(info).map_err(E::custom)?,
+                serde_json::to_value(rating).map_err(E::custom)?,
+            ] {
+                if let serde_json::Value::Object(map) = part {
+                    fields.extend(map);
+                }
+            }
+
+            fields.insert(content_key.to_string(), content);
+
+            let mut root = serde_json::Map::new();
+            root.insert("item".to_string(), serde_json::Value::Object(fields));
+            Ok(serde_json::Value::Object(root))
+        }
+
+        let value = match self {
+            Item::Movie { info, rating, videos } => payload(
+                "movie",
+                info,
+                rating,
+                "videos",
+                serde_json::to_value(videos).map_err(S::Error::custom)?,
+            )?,
+            Item::Other { info, rating, videos } => payload(
+                "other",
+                info,
+                rating,
+                "videos",
+                serde_json::to_value(videos).map_err(S::Error::custom)?,
+            )?,
+            Item::Series { info, rating, seasons } => payload(
+                "serial",
+                info,
+                rating,
+                "seasons",
+                serde_json::to_value(seasons).map_err(S::Error::custom)?,
+            )?,
+            Item::DocSeries { info, rating, seasons } => payload(
+                "docuserial",
+                info,
+                rating,
+                "seasons",
+                serde_json::to_value(seasons).map_err(S::Error::custom)?,
+            )?,
+            Item::TvShow { info, rating, seasons } => payload(
+                "tvshow",
+                info,
+                rating,
+                "seasons",
+                serde_json::to_value(seasons).map_err(S::Error::custom)?,
+            )?,
+        };
+
+        value.serialize(serializer)
+    }
+}
+
+/// Server-side catalog filters for `Api::Items`; every field is optional
+/// and absent ones stay out of the query string entirely.
+#[derive(Debug, Clone, Default)]
+pub struct ItemsFilter {
+    pub genre: Option<u64>,
+    pub kind: Option<String>,
+    pub sort: Option<String>,
+    pub year_from: Option<u16>,
+    pub year_to: Option<u16>,
+    pub limit: Option<usize>,
+    pub page: Option<usize>,
+}
+
 pub enum Api<R> {
     CurrentUser,
     ItemById(u64),
-    Search(String),
+    Items(ItemsFilter),
+    Search {
+        query: String,
+        limit: Option<usize>,
+        page: Option<usize>,
+    },
+    Watching,
+    Bookmarks,
+    BookmarkFolder(u64),
     _Unreachable(std::convert::Infallible, std::marker::PhantomData<R>),
 }
 
-impl<R> ToString for Api<R> {
-    fn to_string(&self) -> String {
+// Manual impl: a derive would demand `R: Clone`, but `R` is only phantom.
+impl<R> Clone for Api<R> {
+    fn clone(&self) -> Self {
         match self {
-            Api::CurrentUser => "v1/user".to_string(),
-            Api::ItemById(id) => format!("v1/items/{}", id),
-            Api::Search(query) => {
-                format!(
-                    "v1/items/search?q={}&perpage=1000",
-                    utf8_percent_encode(query, NON_ALPHANUMERIC)
-                )
+            Api::CurrentUser => Api::CurrentUser,
+            Api::ItemById(id) => Api::ItemById(*id),
+            Api::Items(filter) => Api::Items(filter.clone()),
+            Api::Search { query, limit, page } => Api::Search {
+                query: query.clone(),
+                limit: *limit,
+                page: *page,
+            },
+            Api::Watching => Api::Watching,
+            Api::Bookmarks => Api::Bookmarks,
+            Api::BookmarkFolder(id) => Api::BookmarkFolder(*id),
+            Api::_Unreachable(_, _) => unreachable!(),
+        }
+    }
+}
+
+impl<R> std::fmt::Display for Api<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Api::CurrentUser => write!(f, "v1/user"),
+            Api::ItemById(id) => write!(f, "v1/items/{}", id),
+            Api::Items(filter) => {
+                write!(f, "v1/items?perpage={}", filter.limit.unwrap_or(50))?;
+
+                if let Some(genre) = filter.genre {
+                    write!(f, "&genre={}", genre)?;
+                }
+                if let Some(kind) = &filter.kind {
+                    write!(f, "&type={}", utf8_percent_encode(kind, NON_ALPHANUMERIC))?;
+                }
+                if let Some(sort) = &filter.sort {
+                    write!(f, "&sort={}", utf8_percent_encode(sort, NON_ALPHANUMERIC))?;
+                }
+                if let Some(year) = filter.year_from {
+                    write!(f, "&year_from={}", year)?;
+                }
+                if let Some(year) = filter.year_to {
+                    write!(f, "&year_to={}", year)?;
+                }
+                if let Some(page) = filter.page {
+                    write!(f, "&page={}", page)?;
+                }
+
+                Ok(())
+            }
+            Api::Search { query, limit, page } => {
+                write!(
+                    f,
+                    "v1/items/search?q={}&perpage={}",
+                    form_encode(query),
+                    limit.unwrap_or(1000)
+                )?;
+
+                if let Some(page) = page {
+                    write!(f, "&page={}", page)?;
+                }
+
+                Ok(())
             }
+            Api::Watching => write!(f, "v1/watching/serials"),
+            Api::Bookmarks => write!(f, "v1/bookmarks"),
+            Api::BookmarkFolder(id) => write!(f, "v1/bookmarks/{}", id),
             Api::_Unreachable(_, _) => unreachable!(),
         }
     }
 }
 
+/// `application/x-www-form-urlencoded` style encoding for query-string
+/// values: spaces ride as `+` and everything else outside alphanumerics and
+/// `*-._` is percent-encoded. The search endpoint splits multi-word queries
+/// on `+`, so plain `%20` encoding loses all but the first word.
+fn form_encode(value: &str) -> String {
+    const FORM: &AsciiSet = &NON_ALPHANUMERIC
+        .remove(b'*')
+        .remove(b'-')
+        .remove(b'.')
+        .remove(b'_')
+        .remove(b' ');
+
+    utf8_percent_encode(value, FORM)
+        .to_string()
+        .replace(' ', "+")
+}
+
 pub struct ApiClient<'a> {
     config: &'a Config,
     client: Client,
@@ -195,8 +616,9 @@ pub struct ApiClient<'a> {
 }
 
 impl<'a> ApiClient<'a> {
-    pub fn new(config: &'a Config) -> ApiClient {
-        let client = reqwest::Client::new();
+    pub fn new(config: &'a Config) -> ApiClient<'a> {
+        // The proxy URL is validated at startup, so this cannot fail here.
+        let client = config.http_client().expect("failed to build HTTP client");
         ApiClient {
             config,
             client,
@@ -209,21 +631,460 @@ impl<'a> ApiClient<'a> {
         *token = access_token.to_owned();
     }
 
+    /// The retry-with-backoff layer lives here and nowhere else, and this
+    /// method only issues GETs: they are idempotent, so replaying one after a
+    /// transient failure is always safe. The `Authenticator`'s POSTs must not
+    /// pass through this path — the device-token poll has its own pacing loop
+    /// and blindly resubmitting a token exchange could double-consume a code.
     pub async fn get<R: for<'de> Deserialize<'de>>(&self, api: Api<R>) -> Result<R> {
         self.get_decoded(&api.to_string()).await
     }
 
     async fn get_decoded<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
         let url = self.config.api_url.to_url()?.join(path)?;
-        let mut req_builder = self.client.get(url);
 
-        {
-            let access_token = self.access_token.lock().unwrap();
-            if !access_token.is_empty() {
-                req_builder = req_builder.bearer_auth(access_token);
+        let mut attempt = 0;
+        loop {
+            let mut req_builder = self.client.get(url.clone());
+
+            {
+                let access_token = self.access_token.lock().unwrap();
+                if !access_token.is_empty() {
+                    req_builder = req_builder.bearer_auth(access_token);
+                }
             }
+
+            let delay = match crate::utils::send_traced(&self.client, req_builder).await {
+                Ok(response) if !is_retryable_status(response.status()) => {
+                    if !response.status().is_success() {
+                        return Err(decode_api_error(response).await.into());
+                    }
+
+                    return Ok(response.json().await?);
+                }
+                Ok(response) => retry_after(&response)
+                    .unwrap_or_else(|| self.config.base_delay * 2u32.saturating_pow(attempt)),
+                Err(err) if attempt < self.config.max_retries as u32 => {
+                    log::warn!("request to {} failed: {}", url, err);
+                    self.config.base_delay * 2u32.saturating_pow(attempt)
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            if attempt >= self.config.max_retries as u32 {
+                return Err(anyhow::anyhow!(
+                    "request to {} still failing after {} retries",
+                    url,
+                    self.config.max_retries
+                ));
+            }
+
+            attempt += 1;
+            log::debug!("retrying {} in {:?} (attempt {})", url, delay, attempt);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Error response from the API, carrying the HTTP status and the server's
+/// own message instead of a cryptic JSON decode failure.
+#[derive(Debug, Error)]
+#[error("API error {status}: {message}")]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    pub message: String,
+}
+
+/// Standard error payload shape; anything else degrades to the raw body.
+#[derive(Deserialize)]
+struct ApiErrorBody {
+    // status: u16,
+    message: String,
+}
+
+async fn decode_api_error(response: reqwest::Response) -> ApiError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+
+    let message = serde_json::from_str::<ApiErrorBody>(&body)
+        .map(|e| e.message)
+        .unwrap_or_else(|_| body.trim().to_string());
+
+    ApiError { status, message }
+}
+
+/// Transient statuses worth retrying: server-side errors and rate limiting.
+/// Other client errors fail immediately.
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Server-provided delay from a Retry-After header (seconds form only).
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .map(Duration::from_secs)
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Api, ApiClient, Config, User};
+    use crate::test_util::StubServer;
+
+    fn config_for(server: &StubServer) -> Config {
+        Config {
+            api_url: server.url.clone(),
+            base_delay: std::time::Duration::from_millis(1),
+            ..Config::default()
         }
+    }
+
+    #[test]
+    fn unknown_item_types_still_parse_when_they_carry_videos() {
+        let item: super::Item = serde_json::from_str(
+            r#"{"item": {
+                "type": "concert",
+                "title": "Концерт / The Concert",
+                "year": 2021,
+                "plot": "",
+                "videos": [
+                    {"duration": 5400, "files": [
+                        {"quality": "1080p", "url": {"http": "http://example.com/concert.mp4"}}
+                    ]}
+                ]
+            }}"#,
+        )
+        .unwrap();
+
+        match item {
+            super::Item::Other { info, videos, .. } => {
+                assert_eq!(info.year, 2021);
+                assert_eq!(videos.len(), 1);
+                assert_eq!(videos[0].files[0].quality, "1080p");
+            }
+            other => panic!("expected Item::Other, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn items_url_encodes_present_filters_and_omits_absent_ones() {
+        let api: Api<crate::api::search::SearchResult> = Api::Items(super::ItemsFilter {
+            genre: Some(7),
+            kind: Some("serial".to_string()),
+            year_from: Some(2000),
+            limit: Some(25),
+            page: Some(3),
+            ..super::ItemsFilter::default()
+        });
+
+        let path = api.to_string();
+        assert!(path.starts_with("v1/items?"));
+        assert!(path.contains("perpage=25"));
+        assert!(path.contains("genre=7"));
+        assert!(path.contains("type=serial"));
+        assert!(path.contains("year_from=2000"));
+        assert!(path.contains("page=3"));
+        assert!(!path.contains("sort="));
+        assert!(!path.contains("year_to="));
+
+        let bare: Api<crate::api::search::SearchResult> =
+            Api::Items(super::ItemsFilter::default());
+        assert_eq!(bare.to_string(), "v1/items?perpage=50");
+    }
+
+    #[test]
+    fn search_url_carries_pagination_and_encodes_the_query() {
+        let api: Api<crate::api::search::SearchResult> = Api::Search {
+            query: "война и мир".to_string(),
+            limit: Some(25),
+            page: Some(2),
+        };
+
+        let path = api.to_string();
+        assert!(path.contains("perpage=25"));
+        assert!(path.contains("page=2"));
+        assert!(path.contains("q=%D0%B2%D0%BE%D0%B9%D0%BD%D0%B0+%D0%B8+%D0%BC%D0%B8%D1%80"));
+    }
+
+    #[test]
+    fn search_queries_are_form_encoded() {
+        let query = |q: &str| -> String {
+            let api: Api<crate::api::search::SearchResult> = Api::Search {
+                query: q.to_string(),
+                limit: None,
+                page: None,
+            };
+            api.to_string()
+        };
+
+        // Multi-word: spaces ride as `+`, the form-urlencoded convention.
+        assert_eq!(
+            query("star wars"),
+            "v1/items/search?q=star+wars&perpage=1000"
+        );
+
+        // Cyrillic: percent-encoded UTF-8, words still joined by `+`.
+        assert_eq!(
+            query("война и мир"),
+            "v1/items/search?q=%D0%B2%D0%BE%D0%B9%D0%BD%D0%B0+%D0%B8+%D0%BC%D0%B8%D1%80&perpage=1000"
+        );
+
+        // Specials that would break the query string are escaped; the safe
+        // form characters `*-._` pass through.
+        assert_eq!(
+            query("what's up? 100% *-._"),
+            "v1/items/search?q=what%27s+up%3F+100%25+*-._&perpage=1000"
+        );
+    }
+
+    #[test]
+    fn search_url_defaults_match_the_old_behavior() {
+        let api: Api<crate::api::search::SearchResult> = Api::Search {
+            query: "dune".to_string(),
+            limit: None,
+            page: None,
+        };
+
+        assert_eq!(api.to_string(), "v1/items/search?q=dune&perpage=1000");
+    }
+
+    const USER_BODY: &str = r#"{"user": {"username": "bob", "reg_date": 0, "subscription": {}}}"#;
+
+    #[tokio::test]
+    async fn error_payloads_surface_status_and_message() {
+        let server = StubServer::start(vec![(
+            401,
+            r#"{"status": 401, "message": "token expired"}"#.to_string(),
+        )])
+        .await;
+
+        let config = config_for(&server);
+        let client = ApiClient::new(&config);
+
+        let err = client.get(Api::<User>::CurrentUser).await.unwrap_err();
+        let api_error = err.downcast_ref::<super::ApiError>().unwrap();
+
+        assert_eq!(api_error.status, 401);
+        assert_eq!(api_error.message, "token expired");
+    }
+
+    #[tokio::test]
+    async fn unexpected_error_bodies_fall_back_to_raw_text() {
+        let server = StubServer::start(vec![(404, "item not found".to_string())]).await;
+
+        let config = config_for(&server);
+        let client = ApiClient::new(&config);
+
+        let err = client.get(Api::<User>::CurrentUser).await.unwrap_err();
+        let api_error = err.downcast_ref::<super::ApiError>().unwrap();
+
+        assert_eq!(api_error.status, 404);
+        assert_eq!(api_error.message, "item not found");
+    }
+
+    #[test]
+    fn api_overrides_replace_defaults_and_none_keeps_them() {
+        let mut config = Config::default();
+        config.set_api_overrides(
+            Some("https://mirror.example/".to_string()),
+            Some("test-client".to_string()),
+            None,
+        );
+
+        assert_eq!(config.api_url, "https://mirror.example/");
+        assert_eq!(config.client_id, "test-client");
+        assert_eq!(config.client_secret, Config::default().client_secret);
+
+        // Built request URLs point at the override host.
+        let url = format!("{}{}", config.api_url, Api::<User>::CurrentUser);
+        assert!(url.starts_with("https://mirror.example/v1/user"));
+    }
+
+    #[tokio::test]
+    async fn slow_responses_error_after_the_configured_timeout() {
+        let server = StubServer::start_with_delay(
+            vec![(200, USER_BODY.to_string())],
+            std::time::Duration::from_secs(5),
+        )
+        .await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            request_timeout: std::time::Duration::from_millis(100),
+            ..Config::default()
+        };
+
+        let client = ApiClient::new(&config);
+        let result: Result<User, _> = client.get(Api::CurrentUser).await;
+
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "got: {}", err);
+    }
+
+    #[tokio::test]
+    async fn requests_route_through_the_configured_proxy() {
+        let proxy = StubServer::start(vec![(200, "proxied".to_string())]).await;
+
+        let config = Config {
+            proxy: Some(proxy.url.trim_end_matches('/').to_string()),
+            ..Config::default()
+        };
+
+        let client = config.http_client().unwrap();
+        let body = client
+            .get("http://item.test/movie")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert_eq!(proxy.hits(), 1);
+        assert_eq!(body, "proxied");
+    }
+
+    #[test]
+    fn items_serialize_back_to_the_wire_shape() {
+        let item = crate::app::tests::series_fixture();
+
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(json["item"]["type"], "serial");
+        assert_eq!(json["item"]["title"], "Сериал / The Series");
+        assert_eq!(json["item"]["seasons"][0]["episodes"][0]["number"], 1);
+
+        // The dump loads back through the ordinary deserializer, unchanged.
+        let reparsed: super::Item = serde_json::from_value(json.clone()).unwrap();
+        assert!(matches!(reparsed, super::Item::Series { .. }));
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+    }
+
+    #[test]
+    fn no_proxy_entries_match_hosts_and_domains() {
+        use super::host_bypasses_proxy;
+
+        assert!(host_bypasses_proxy("api.service-kp.com", "api.service-kp.com"));
+        assert!(host_bypasses_proxy("api.service-kp.com", "service-kp.com"));
+        assert!(host_bypasses_proxy("api.service-kp.com", ".service-kp.com"));
+        assert!(host_bypasses_proxy("api.service-kp.com", "example.com, service-kp.com"));
+        assert!(host_bypasses_proxy("anything.test", "*"));
+        assert!(host_bypasses_proxy("127.0.0.1", "localhost, 127.0.0.1"));
+
+        assert!(!host_bypasses_proxy("api.service-kp.com", ""));
+        assert!(!host_bypasses_proxy("notservice-kp.com", "service-kp.com"));
+        assert!(!host_bypasses_proxy("api.service-kp.com", "example.com"));
+    }
+
+    #[tokio::test]
+    async fn no_proxy_bypasses_the_configured_proxy_for_excluded_hosts() {
+        let server = StubServer::start(vec![(200, "direct".to_string())]).await;
+
+        // The proxy address is a closed port: only a bypassed request can
+        // succeed, so a passing test proves NO_PROXY took effect.
+        let config = Config {
+            proxy: Some("http://127.0.0.1:9".to_string()),
+            request_timeout: std::time::Duration::from_millis(500),
+            ..Config::default()
+        };
+
+        std::env::set_var("NO_PROXY", "127.0.0.1");
+        let client = config.http_client().unwrap();
+        std::env::remove_var("NO_PROXY");
+
+        let body = client
+            .get(&server.url)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "direct");
+
+        // Without the exclusion the request dies on the unreachable proxy.
+        let client = config.http_client().unwrap();
+        assert!(client.get(&server.url).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ip_family_preference_constrains_connections() {
+        use super::IpFamily;
+
+        assert_eq!(
+            IpFamily::V4.local_address(),
+            std::net::IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED)
+        );
+        assert_eq!(
+            IpFamily::V6.local_address(),
+            std::net::IpAddr::V6(std::net::Ipv6Addr::UNSPECIFIED)
+        );
+
+        // The stub listens on IPv4 loopback: a V4-bound client reaches it, a
+        // V6-bound one cannot.
+        let server = StubServer::start(vec![(200, "{}".to_string())]).await;
+
+        let mut config = Config {
+            api_url: server.url.clone(),
+            ..Config::default()
+        };
+
+        config.set_ip_family(Some(IpFamily::V4));
+        let client = config.http_client().unwrap();
+        assert!(client.get(&server.url).send().await.is_ok());
+
+        config.set_ip_family(Some(IpFamily::V6));
+        let client = config.http_client().unwrap();
+        assert!(client.get(&server.url).send().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_carry_the_configured_user_agent() {
+        let server = StubServer::start(vec![(200, USER_BODY.to_string())]).await;
+
+        let config = Config {
+            user_agent: "test-agent/9.9".to_string(),
+            ..config_for(&server)
+        };
+        let client = ApiClient::new(&config);
+        let _: User = client.get(Api::CurrentUser).await.unwrap();
+
+        let request = server.requests().remove(0);
+        assert!(request
+            .lines()
+            .any(|line| line.eq_ignore_ascii_case("user-agent: test-agent/9.9")));
+    }
+
+    #[tokio::test]
+    async fn retries_transient_server_errors() {
+        let server = StubServer::start(vec![
+            (503, String::new()),
+            (503, String::new()),
+            (200, USER_BODY.to_string()),
+        ])
+        .await;
+
+        let config = config_for(&server);
+        let client = ApiClient::new(&config);
+        let user: User = client.get(Api::CurrentUser).await.unwrap();
+
+        assert_eq!(user.username, "bob");
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let server = StubServer::start(vec![(404, String::new())]).await;
+
+        let config = config_for(&server);
+        let client = ApiClient::new(&config);
+        let result: Result<User, _> = client.get(Api::CurrentUser).await;
 
-        Ok(req_builder.send().await?.json().await?)
+        assert!(result.is_err());
+        assert_eq!(server.hits(), 1);
     }
 }
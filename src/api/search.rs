@@ -1,13 +1,13 @@
 use cli_table::{format::Justify, Table};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Genre {
     // id: u64,
     title: String,
 }
 
-#[derive(Debug, Deserialize, Table)]
+#[derive(Debug, Deserialize, Serialize, Table)]
 pub struct SearchResultItem {
     #[table(title = "ID", justify = "Justify::Right")]
     pub id: u64,
@@ -20,9 +20,17 @@ pub struct SearchResultItem {
     #[serde(rename = "plot")]
     #[table(skip)]
     pub description: String,
-    #[table(skip)]
+    #[table(
+        title = "IMDB",
+        display_fn = "render_rating",
+        justify = "Justify::Right"
+    )]
     pub imdb_rating: Option<f32>,
-    #[table(skip)]
+    #[table(
+        title = "Kinopoisk",
+        display_fn = "render_rating",
+        justify = "Justify::Right"
+    )]
     pub kinopoisk_rating: Option<f32>,
 
     #[table(title = "Genre", display_fn = "render_genres")]
@@ -32,6 +40,40 @@ pub struct SearchResultItem {
 #[derive(Debug, Deserialize)]
 pub struct SearchResult {
     pub items: Vec<SearchResultItem>,
+    #[serde(default)]
+    pub pagination: Option<Pagination>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    pub total: Option<u64>,
+}
+
+/// Client-side `--type` filter: keeps items whose `type` matches any of the
+/// comma-separated kinds. The API mixes all types into one result list, so
+/// this runs on the already deserialized items.
+pub fn filter_by_type(items: Vec<SearchResultItem>, kinds: &str) -> Vec<SearchResultItem> {
+    let kinds: Vec<_> = kinds
+        .split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .collect();
+
+    if kinds.is_empty() {
+        return items;
+    }
+
+    items
+        .into_iter()
+        .filter(|item| kinds.iter().any(|kind| item.r#type.eq_ignore_ascii_case(kind)))
+        .collect()
+}
+
+fn render_rating(rating: &Option<f32>) -> String {
+    match rating {
+        Some(rating) => format!("{:.1}", rating),
+        None => "-".to_string(),
+    }
 }
 
 fn render_genres(items: &[Genre]) -> String {
@@ -41,3 +83,79 @@ fn render_genres(items: &[Genre]) -> String {
         .collect::<Vec<_>>()
         .join(", ")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_by_type, render_rating, SearchResultItem};
+
+    #[test]
+    fn formats_present_and_absent_ratings() {
+        assert_eq!(render_rating(&Some(7.8)), "7.8");
+        assert_eq!(render_rating(&Some(7.0)), "7.0");
+        assert_eq!(render_rating(&None), "-");
+    }
+
+    #[test]
+    fn type_filter_keeps_only_the_requested_kinds() {
+        let mixed = || -> Vec<SearchResultItem> {
+            ["movie", "serial", "tvshow", "movie"]
+                .iter()
+                .enumerate()
+                .map(|(id, kind)| {
+                    serde_json::from_str(&format!(
+                        r#"{{
+                            "id": {},
+                            "title": "Item",
+                            "year": 2020,
+                            "type": "{}",
+                            "plot": "",
+                            "genres": []
+                        }}"#,
+                        id, kind
+                    ))
+                    .unwrap()
+                })
+                .collect()
+        };
+
+        let movies = filter_by_type(mixed(), "movie");
+        assert_eq!(movies.len(), 2);
+        assert!(movies.iter().all(|item| item.r#type == "movie"));
+
+        let two_kinds = filter_by_type(mixed(), "Serial, tvshow");
+        assert_eq!(two_kinds.len(), 2);
+        assert!(two_kinds.iter().all(|item| item.r#type != "movie"));
+
+        // A blank filter is a no-op rather than an empty result.
+        assert_eq!(filter_by_type(mixed(), " ").len(), 4);
+    }
+
+    #[test]
+    fn json_output_round_trips() {
+        let item: SearchResultItem = serde_json::from_str(
+            r#"{
+                "id": 42,
+                "title": "Some Movie",
+                "year": 2020,
+                "type": "movie",
+                "plot": "A plot.",
+                "imdb_rating": 7.8,
+                "kinopoisk_rating": null,
+                "genres": [{"title": "drama"}]
+            }"#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_string_pretty(&item).unwrap();
+        assert!(json.contains("\"id\": 42"));
+        assert!(json.contains("\"title\": \"Some Movie\""));
+        assert!(json.contains("\"imdb_rating\": 7.8"));
+
+        let back: SearchResultItem = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.id, item.id);
+        assert_eq!(back.title, item.title);
+        assert_eq!(back.description, item.description);
+        assert_eq!(back.imdb_rating, item.imdb_rating);
+        assert_eq!(back.kinopoisk_rating, item.kinopoisk_rating);
+    }
+}
@@ -0,0 +1,61 @@
+use cli_table::{format::Justify, Table};
+use serde::Deserialize;
+
+/// One in-progress series from `v1/watching/serials`, with enough state to
+/// tell the user where to pick up again.
+#[derive(Debug, Deserialize, Table)]
+pub struct WatchingItem {
+    #[table(title = "ID", justify = "Justify::Right")]
+    pub id: u64,
+    #[table(title = "Title")]
+    pub title: String,
+    #[serde(default)]
+    #[table(
+        title = "Next episode",
+        display_fn = "render_next_episode",
+        justify = "Justify::Right"
+    )]
+    pub watched: u64,
+    #[serde(default)]
+    #[table(title = "Unwatched", justify = "Justify::Right")]
+    pub new: u64,
+    #[serde(default)]
+    #[table(title = "Total", justify = "Justify::Right")]
+    pub total: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WatchingResult {
+    pub items: Vec<WatchingItem>,
+}
+
+/// Episodes are watched from the front, so the next unwatched absolute
+/// episode is simply one past the watched count.
+fn render_next_episode(watched: &u64) -> String {
+    (watched + 1).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_next_episode, WatchingResult};
+
+    #[test]
+    fn deserializes_a_watching_response() {
+        let result: WatchingResult = serde_json::from_str(
+            r#"{
+                "status": 200,
+                "items": [
+                    {"id": 101, "title": "Сериал / The Series", "watched": 12, "new": 3, "total": 24},
+                    {"id": 102, "title": "Другой / Another", "new": 1}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].id, 101);
+        assert_eq!(result.items[0].watched, 12);
+        assert_eq!(result.items[1].watched, 0);
+        assert_eq!(render_next_episode(&result.items[0].watched), "13");
+    }
+}
@@ -1,14 +1,28 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use std::sync::Arc;
+
+use clap::{ArgEnum, Parser, Subcommand};
+use futures::StreamExt;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use cli_table::{format::Justify, print_stdout, Table, WithTitle};
 use serde::Deserialize;
+use thiserror::Error;
 
 use auth::Authenticator;
 
-use crate::api::search::{SearchResult, SearchResultItem};
-use crate::api::{Api, ApiClient, Config, Item, User};
+use crate::api::bookmarks::{BookmarkFolder, BookmarkFoldersResult, BookmarkItemsResult};
+use crate::api::search::SearchResult;
+use crate::api::watching::WatchingResult;
+use crate::api::{
+    Api, ApiClient, ApiError, Config, Item, ItemsFilter, MovieFile, SeriesSeason, User,
+};
 use crate::auth::storage::TokenStorage;
-use crate::utils::Utils;
-use crate::{auth, parallel_downloader::Downloader};
+use crate::selector::EpisodeSelector;
+use crate::utils::{self, HashAlgorithm, Utils};
+use crate::auth;
+use crate::parallel_downloader::{Downloader, HeadInfo};
 
 #[derive(Parser)]
 #[clap(author = "Vitali Stsepaniuk <contact@vitaliy.dev>", version, about)]
@@ -19,35 +33,462 @@ pub struct Cli {
     #[clap(short, long, parse(from_occurrences))]
     pub verbose: usize,
 
-    #[clap(short, long, default_value_t = 4)]
-    pub threads: u64,
+    #[clap(
+        long,
+        help = "Write log output to this file instead of the terminal"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[clap(
+        short,
+        long,
+        help = "Number of download threads (1-64) or 'auto', default: 4",
+        parse(try_from_str = crate::utils::parse_thread_count)
+    )]
+    pub threads: Option<u64>,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "file",
+        help = "Where to persist the auth tokens"
+    )]
+    pub token_store: TokenStore,
+
+    #[clap(
+        long,
+        help = "Proxy URL for all HTTP traffic, e.g. http://host:3128 or socks5://host:1080"
+    )]
+    pub proxy: Option<String>,
+
+    #[clap(
+        long,
+        help = "Connect directly, ignoring --proxy and proxy environment variables",
+        conflicts_with = "proxy"
+    )]
+    pub no_proxy: bool,
+
+    #[clap(
+        long,
+        help = "Force connections over IPv4 on dual-stack networks",
+        conflicts_with = "prefer-ipv6"
+    )]
+    pub prefer_ipv4: bool,
+
+    #[clap(long, help = "Force connections over IPv6 on dual-stack networks")]
+    pub prefer_ipv6: bool,
+
+    #[clap(
+        long,
+        help = "Idle connections kept per host in the HTTP pool, default: 8"
+    )]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Seconds an idle pooled connection is kept alive, default: 90"
+    )]
+    pub pool_idle_timeout: Option<u64>,
+
+    #[clap(long, help = "Named account whose stored login to use")]
+    pub account: Option<String>,
+
+    #[clap(long, help = "Show a QR code for the device authorization URL")]
+    pub qr: bool,
+
+    #[clap(
+        long,
+        help = "Config file with persistent defaults, default: ~/.config/kinopub-downloader.toml"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[clap(long, help = "No progress bars; log percentage lines instead")]
+    pub quiet: bool,
+
+    #[clap(long, env = "KINOPUB_API_URL", help = "Override the API base URL")]
+    pub api_url: Option<String>,
+
+    #[clap(long, env = "KINOPUB_CLIENT_ID", help = "Override the OAuth client id")]
+    pub client_id: Option<String>,
+
+    #[clap(
+        long,
+        env = "KINOPUB_CLIENT_SECRET",
+        hide_env_values = true,
+        help = "Override the OAuth client secret"
+    )]
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ArgEnum)]
+pub enum TokenStore {
+    File,
+    Keyring,
 }
 
 #[derive(Subcommand)]
+// The Download variant carries every flag of the busiest subcommand; the
+// enum exists once per process, so the size imbalance is harmless.
+#[allow(clippy::large_enum_variant)]
 pub enum Commands {
     Download {
-        #[clap(short = 'i', long = "id", help = "Item ID")]
-        id: u64,
-        #[clap(short = 'q', long, help = "Quality (2160p, 1080p, 720p, 480p)")]
+        #[clap(
+            short = 'i',
+            long = "id",
+            help = "Item ID or kino.pub URL, e.g. https://kino.pub/item/view/12345/s5e3",
+            parse(try_from_str = std::str::FromStr::from_str)
+        )]
+        id: ItemRef,
+        #[clap(
+            short = 'q',
+            long,
+            help = "Quality (2160p, 1080p, 720p, 480p), or 'max' for the best available"
+        )]
         quality: Option<String>,
         #[clap(
             short = 's',
             long,
-            help = "Season # (only for TV series), default: all"
+            help = "Season selection (e.g. 2, 1-3, 1,3, all), only for TV series, default: all"
         )]
-        season: Option<usize>,
+        season: Option<EpisodeSelector>,
         #[clap(
             short = 'e',
             long,
-            help = "Episode # (only for TV series), default: all"
+            help = "Episode selection (e.g. 5, 3-7, 1,4,9, all), only for TV series, default: all"
+        )]
+        episode: Option<EpisodeSelector>,
+        #[clap(
+            long,
+            help = "Seasons to skip, same syntax as --season; wins over inclusion"
+        )]
+        exclude_season: Option<EpisodeSelector>,
+        #[clap(
+            long,
+            help = "Episodes to skip, same syntax as --episode; wins over inclusion"
+        )]
+        exclude_episode: Option<EpisodeSelector>,
+        #[clap(
+            long,
+            help = "Absolute episode number counted across seasons, only for TV series",
+            conflicts_with_all = &["season", "episode"]
+        )]
+        absolute: Option<usize>,
+        #[clap(
+            long,
+            help = "Fall back to positional matching when --season/--episode numbers have gaps"
+        )]
+        by_index: bool,
+        #[clap(long, help = "Which part of a multi-part movie to download, 1-based")]
+        part: Option<usize>,
+        #[clap(
+            long,
+            help = "Filename template without extension, e.g. '{title} ({year}) s{season:02}e{episode:02}'"
+        )]
+        name_template: Option<String>,
+        #[clap(long, help = "Skip the free-disk-space check before downloading")]
+        no_space_check: bool,
+        #[clap(
+            short = 'o',
+            long,
+            help = "Exact output path, only for selections that resolve to one file"
+        )]
+        output: Option<PathBuf>,
+        #[clap(long, help = "Directory to save files into, default: current")]
+        output_dir: Option<PathBuf>,
+        #[clap(long, help = "Do not create per-season subfolders for series")]
+        flat: bool,
+        #[clap(long, help = "List available qualities instead of downloading")]
+        list_qualities: bool,
+        #[clap(
+            long,
+            help = "Fall back to the closest available quality when the requested one is missing"
+        )]
+        fallback_quality: bool,
+        #[clap(
+            long,
+            help = "Cap aggregate download speed in bytes/sec, e.g. 500K or 2M",
+            parse(try_from_str = crate::utils::parse_byte_size)
+        )]
+        max_rate: Option<u64>,
+        #[clap(
+            long,
+            help = "Cap each connection's speed in bytes/sec; with --max-rate the more restrictive wins",
+            parse(try_from_str = crate::utils::parse_byte_size)
+        )]
+        limit_rate_per_connection: Option<u64>,
+        #[clap(
+            long,
+            help = "Download in fixed-size chunks, e.g. 16M; --threads then only limits concurrency",
+            parse(try_from_str = crate::utils::parse_byte_size)
+        )]
+        chunk_size: Option<u64>,
+        #[clap(
+            long,
+            help = "Download only the first SIZE bytes, e.g. 50M, for sampling",
+            parse(try_from_str = crate::utils::parse_byte_size),
+            conflicts_with = "range"
+        )]
+        head: Option<u64>,
+        #[clap(
+            long,
+            help = "Download only this byte range, e.g. 0-100M",
+            parse(try_from_str = crate::utils::parse_byte_range)
+        )]
+        range: Option<(u64, u64)>,
+        #[clap(long, help = "Print the resolved download plan and exit")]
+        dry_run: bool,
+        #[clap(
+            long,
+            help = "Print the direct media URLs (one per line) instead of downloading"
+        )]
+        print_url: bool,
+        #[clap(
+            long,
+            help = "Like --print-url, with the filename as a tab-separated second column"
+        )]
+        print_url_with_name: bool,
+        #[clap(long, help = "Skip files that already exist with the expected size")]
+        skip_existing: bool,
+        #[clap(
+            long,
+            help = "Replace existing files instead of refusing to touch them",
+            conflicts_with = "no-clobber"
+        )]
+        overwrite: bool,
+        #[clap(
+            long,
+            help = "Refuse to replace existing files (the default; kept for explicit scripts)"
+        )]
+        no_clobber: bool,
+        #[clap(long, help = "Write a Kodi/Jellyfin .nfo sidecar next to each video")]
+        write_nfo: bool,
+        #[clap(long, help = "Download the poster image next to each video")]
+        write_poster: bool,
+        #[clap(
+            long,
+            help = "Download only episodes not already on disk under their default names"
+        )]
+        new_episodes: bool,
+        #[clap(long, help = "Write the item's raw metadata as JSON to this path")]
+        save_json: Option<PathBuf>,
+        #[clap(
+            long,
+            default_value_t = 3,
+            help = "How many times to retry a failed or stalled chunk"
         )]
-        episode: Option<usize>,
+        retries: u64,
+        #[clap(
+            long,
+            help = "Fail a chunk when no data arrives for this many seconds"
+        )]
+        timeout_secs: Option<u64>,
+        #[clap(long, help = "Expected SHA-256 of the downloaded file, hex-encoded")]
+        sha256: Option<String>,
+        #[clap(long, help = "Expected MD5 of the downloaded file, hex-encoded")]
+        md5: Option<String>,
+        #[clap(
+            long,
+            default_value_t = 1,
+            help = "Download up to N episodes at once"
+        )]
+        parallel_items: usize,
+        #[clap(
+            long,
+            help = "Also download subtitles, optionally only for one language",
+            min_values = 0,
+            max_values = 1
+        )]
+        subtitles: Option<Vec<String>>,
+        #[clap(long, help = "Prefer files with this audio track (dubbing studio)")]
+        audio: Option<String>,
+        #[clap(
+            long,
+            help = "Audio language preference as an ISO code (e.g. ru, en); soft filter"
+        )]
+        lang: Option<String>,
+        #[clap(long, help = "Prefer files with this codec, e.g. h264 or hevc")]
+        codec: Option<String>,
+    },
+    Info {
+        #[clap(short = 'i', long = "id", help = "Item ID")]
+        id: u64,
+        #[clap(long, help = "Write the item's raw metadata as JSON to this path")]
+        save_json: Option<PathBuf>,
+    },
+    Accounts {
+        #[clap(subcommand)]
+        command: AccountsCommand,
     },
     Authenticate,
+    Logout,
     Search {
         #[clap(short = 'q', long, help = "Search query")]
         query: String,
+        #[clap(
+            long = "type",
+            help = "Only show these result types, comma-separated (movie, serial, tvshow, ...)"
+        )]
+        kind: Option<String>,
+        #[clap(long, help = "Output results as JSON instead of a table")]
+        json: bool,
+        #[clap(short = 'l', long, help = "Maximum number of results per page")]
+        limit: Option<usize>,
+        #[clap(short = 'p', long, help = "Result page to fetch")]
+        page: Option<usize>,
+    },
+    Interactive {
+        #[clap(short = 'q', long, help = "Search query")]
+        query: String,
+    },
+    Browse {
+        #[clap(long, help = "Genre id to filter by")]
+        genre: Option<u64>,
+        #[clap(long = "type", help = "Item type: movie, serial, docuserial, tvshow")]
+        kind: Option<String>,
+        #[clap(long, help = "Server-side sort order, e.g. -created or rating")]
+        sort: Option<String>,
+        #[clap(long, help = "Only items released in or after this year")]
+        year_from: Option<u16>,
+        #[clap(long, help = "Only items released in or before this year")]
+        year_to: Option<u16>,
+        #[clap(short = 'l', long, help = "Maximum number of results per page")]
+        limit: Option<usize>,
+        #[clap(short = 'p', long, help = "Result page to fetch")]
+        page: Option<usize>,
+    },
+    Update {
+        #[clap(
+            long,
+            help = "Exit with a non-zero status when a newer release exists"
+        )]
+        check: bool,
     },
+    Watching,
+    Doctor,
+    Bookmarks {
+        #[clap(long, help = "Folder title or id; omit to list the folders themselves")]
+        folder: Option<String>,
+        #[clap(subcommand)]
+        command: Option<BookmarksCommand>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AccountsCommand {
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum BookmarksCommand {
+    /// Download every item in the selected folder.
+    Download,
+}
+
+/// Item reference from the CLI: a bare numeric id, or a kino.pub URL that may
+/// also carry a season/episode selection.
+#[derive(Debug, Clone)]
+pub struct ItemRef {
+    pub id: u64,
+    pub season: Option<usize>,
+    pub episode: Option<usize>,
+}
+
+impl std::str::FromStr for ItemRef {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (id, season, episode) = crate::utils::parse_item_ref(value)?;
+        Ok(Self {
+            id,
+            season,
+            episode,
+        })
+    }
+}
+
+/// Options for a single `download` invocation, mirroring the CLI flags.
+#[derive(Debug, Default)]
+pub struct DownloadOptions {
+    pub quality: Option<String>,
+    /// Extra quality synonyms from the config file, applied on top of the
+    /// built-in alias table.
+    pub quality_aliases: std::collections::HashMap<String, String>,
+    pub season: Option<EpisodeSelector>,
+    pub episode: Option<EpisodeSelector>,
+    /// Seasons and episodes to drop from the selection. Exclusion runs after
+    /// inclusion, so a number matched by both selectors is skipped.
+    pub exclude_season: Option<EpisodeSelector>,
+    pub exclude_episode: Option<EpisodeSelector>,
+    /// 1-based episode number counted across all seasons; overrides the
+    /// season/episode selectors when set.
+    pub absolute: Option<usize>,
+    /// 1-based video index for multi-part movies; absent downloads all parts.
+    pub part: Option<usize>,
+    /// When the season/episode selectors match none of the listed `number`s
+    /// (gapped or zero-based listings), match them as 1-based positions.
+    pub by_index: bool,
+    pub name_template: Option<String>,
+    pub no_space_check: bool,
+    pub output: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub flat: bool,
+    pub list_qualities: bool,
+    pub fallback_quality: bool,
+    pub max_rate: Option<u64>,
+    pub limit_rate_per_connection: Option<u64>,
+    pub chunk_size: Option<u64>,
+    /// Partial download: only the first N bytes (--head) ...
+    pub head: Option<u64>,
+    /// ... or an explicit half-open byte window (--range).
+    pub range: Option<(u64, u64)>,
+    pub dry_run: bool,
+    pub print_url: bool,
+    pub print_url_with_name: bool,
+    pub skip_existing: bool,
+    /// Existing files are never truncated unless this is set; re-runs fail
+    /// early instead of silently wiping a finished download.
+    pub overwrite: bool,
+    pub write_nfo: bool,
+    pub write_poster: bool,
+    /// Incremental sync: episodes whose default-named files are already in
+    /// the output directory are dropped from the selection.
+    pub new_episodes: bool,
+    /// Dump the item metadata, re-serialized to the wire shape, to this path.
+    pub save_json: Option<PathBuf>,
+    pub retries: u64,
+    pub timeout_secs: Option<u64>,
+    pub sha256: Option<String>,
+    pub md5: Option<String>,
+    pub parallel_items: usize,
+    /// `Some(None)` downloads every language, `Some(Some(lang))` only one.
+    pub subtitles: Option<Option<String>>,
+    pub audio: Option<String>,
+    /// ISO language code preference for the audio, e.g. "ru" or "en".
+    pub lang: Option<String>,
+    pub codec: Option<String>,
+    pub quiet: bool,
+}
+
+/// One file the current selection resolves to, before any transfer happens.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResolvedFile {
+    pub title: String,
+    pub url: String,
+    pub relative_path: PathBuf,
+}
+
+/// Anything a download can be resolved from. `App::download` only ever
+/// consumes the resolved list, so selection logic (this trait) and transfer
+/// logic (`Downloader`) stay independently testable and other catalog shapes
+/// can plug in without touching the download path.
+pub trait MediaSource {
+    fn resolve_files(&self, options: &DownloadOptions) -> Result<Vec<ResolvedFile>>;
+}
+
+impl MediaSource for Item {
+    fn resolve_files(&self, options: &DownloadOptions) -> Result<Vec<ResolvedFile>> {
+        resolve_files(self, options)
+    }
 }
 
 pub struct App<'a, Storage>
@@ -57,6 +498,7 @@ where
     auth: Authenticator<'a, Storage>,
     api_client: ApiClient<'a>,
     config: &'a Config,
+    storage: &'a Storage,
 }
 
 impl<'a, Storage> App<'a, Storage>
@@ -70,88 +512,2930 @@ where
             auth,
             api_client,
             config,
+            storage,
         }
     }
 
+    /// Records the account's username on the stored token so later runs can
+    /// show which account is logged in without an API round-trip.
+    pub fn remember_username(&self, username: &str) -> Result<()> {
+        if let Some(mut data) = self.storage.get_data() {
+            if data.username.as_deref() != Some(username) {
+                data.username = Some(username.to_string());
+                self.storage.set(&data)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn current_user(&self) -> Result<User> {
         self.request(Api::CurrentUser).await
     }
 
-    pub async fn search(&self, query: &str) -> Result<Vec<SearchResultItem>> {
-        self.request(Api::Search(query.to_string()))
-            .await
-            .map(|r: SearchResult| r.items)
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: Option<usize>,
+        page: Option<usize>,
+    ) -> Result<SearchResult> {
+        self.request(Api::Search {
+            query: query.to_string(),
+            limit,
+            page,
+        })
+        .await
     }
 
-    pub async fn download(
-        &self,
-        id: u64,
-        quality: Option<String>,
-        season: Option<usize>,
-        episode: Option<usize>,
-    ) -> Result<()> {
+    /// Catalog listing with server-side filters; the response shares the
+    /// search result shape, so the same table renders both.
+    pub async fn browse(&self, filter: ItemsFilter) -> Result<SearchResult> {
+        self.request(Api::Items(filter)).await
+    }
+
+    pub async fn watching(&self) -> Result<WatchingResult> {
+        self.request(Api::Watching).await
+    }
+
+    pub async fn bookmark_folders(&self) -> Result<BookmarkFoldersResult> {
+        self.request(Api::Bookmarks).await
+    }
+
+    pub async fn bookmark_items(&self, folder_id: u64) -> Result<BookmarkItemsResult> {
+        self.request(Api::BookmarkFolder(folder_id)).await
+    }
+
+    /// Resolves a folder given on the command line, accepting either its
+    /// numeric id or a case-insensitive title match.
+    pub async fn resolve_bookmark_folder(&self, folder: &str) -> Result<BookmarkFolder> {
+        let folders = self.bookmark_folders().await?.items;
+
+        folders
+            .into_iter()
+            .find(|f| {
+                f.title.eq_ignore_ascii_case(folder)
+                    || folder.parse::<u64>().map(|id| id == f.id).unwrap_or(false)
+            })
+            .ok_or_else(|| anyhow!("no bookmark folder named '{}'", folder))
+    }
+
+    pub async fn info(&self, id: u64, save_json: Option<&Path>) -> Result<()> {
+        let item: Item = self.request(Api::ItemById(id)).await?;
+
+        if let Some(path) = save_json {
+            save_item_json(&item, path)?;
+        }
+
+        println!("{}", render_info(&item));
+        print_stdout(info_rows(&item).with_title())?;
+
+        Ok(())
+    }
+
+    pub async fn download(&self, id: u64, mut options: DownloadOptions) -> Result<()> {
         let item: &Item = &self.request(Api::ItemById(id)).await?;
-        let quality = quality.unwrap_or_else(|| "720p".to_owned());
-
-        match item {
-            Item::Movie { videos, .. } => {
-                if let Some(file) = videos
-                    .first()
-                    .and_then(|v| v.files.iter().find(|f| f.quality == quality))
-                {
-                    let filename = Utils::generate_filename(item, &quality, season, episode)?;
-
-                    return self
-                        .download_single_file(&filename, &file.url.http, &filename)
-                        .await;
+
+        if let Some(path) = &options.save_json {
+            save_item_json(item, path)?;
+        }
+
+        if options.list_qualities {
+            return list_qualities(item);
+        }
+
+        if let Some(absolute) = options.absolute {
+            let seasons = match item {
+                Item::Series { seasons, .. }
+                | Item::TvShow { seasons, .. }
+                | Item::DocSeries { seasons, .. } => seasons,
+                _ => bail!("--absolute only applies to series"),
+            };
+
+            let (season, episode) = map_absolute_episode(seasons, absolute)?;
+            options.season = Some(EpisodeSelector::single(season));
+            options.episode = Some(EpisodeSelector::single(episode));
+        }
+
+        let mut files = MediaSource::resolve_files(item, &options)?;
+
+        if let Some(output) = &options.output {
+            apply_output_override(&mut files, output)?;
+        }
+
+        // Incremental sync happens before the plan and space checks, so both
+        // only ever see the episodes that will actually be fetched. The scan
+        // must not create the output directory, hence no resolve_output_dir.
+        if options.new_episodes {
+            let scan_dir = match options.output_dir.clone() {
+                Some(dir) => dir,
+                None => std::env::current_dir()?,
+            };
+            let existing = existing_episodes(&scan_dir, item);
+            files.retain(|file| is_new_episode(file, &existing));
+        }
+
+        if options.print_url || options.print_url_with_name {
+            for line in url_lines(&files, options.print_url_with_name) {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+
+        // One bounded pass of HEAD probes feeds the dry-run listing, the
+        // free-space check, and the overall total, instead of a serial probe
+        // per file. Unknown sizes just do not count towards the estimate.
+        let probes = if options.dry_run || !options.no_space_check {
+            probe_files(&files, options.parallel_items, self.config.http_client()?).await
+        } else {
+            files.iter().map(|_| None).collect()
+        };
+        let known_bytes: u64 = probes.iter().flatten().map(|head| head.content_length).sum();
+
+        if options.dry_run {
+            return print_plan(&files, &probes);
+        }
+
+        let output_dir = resolve_output_dir(options.output_dir.clone())?;
+
+        if !options.no_space_check {
+            check_free_space(known_bytes, fs2::available_space(&output_dir)?)?;
+        }
+
+        // Any multi-file download shares one progress group, so bars (and
+        // chunk retry logs) do not interleave on the terminal.
+        let multi_progress =
+            (files.len() > 1 && !options.quiet).then(|| Arc::new(MultiProgress::new()));
+
+        // An outer "file 4/24" bar above the per-file ones, so a long series
+        // download has a sense of overall progress.
+        let overall = multi_progress
+            .as_ref()
+            .map(|multi| overall_progress(multi, files.len(), known_bytes));
+
+        let started = std::time::Instant::now();
+        let total_bytes = Arc::new(std::sync::atomic::AtomicU64::new(0));
+
+        let result = run_downloads(&files, options.parallel_items, |file| {
+            let overall = overall.clone();
+            let multi_progress = multi_progress.clone();
+            let total_bytes = total_bytes.clone();
+            let output_dir = &output_dir;
+            let options = &options;
+
+            async move {
+                let bytes = self
+                    .download_single_file(
+                        &file.title,
+                        &file.url,
+                        &file.relative_path,
+                        output_dir,
+                        options,
+                        multi_progress,
+                    )
+                    .await?;
+
+                total_bytes.fetch_add(bytes, std::sync::atomic::Ordering::SeqCst);
+
+                if let Some(overall) = &overall {
+                    overall.inc(1);
                 }
 
-                eprintln!("File with {} quality is not found.", quality);
-                std::process::exit(1);
+                Ok(())
             }
-            Item::Series { seasons, .. }
-            | Item::TvShow { seasons, .. }
-            | Item::DocSeries { seasons, .. } => {
-                for s in seasons {
-                    if season.is_some() && season.unwrap() != s.number {
-                        continue;
-                    }
+        })
+        .await;
 
-                    for e in s.episodes.iter() {
-                        if episode.is_some() && episode.unwrap() != e.number {
-                            continue;
-                        }
+        if let Some(overall) = overall {
+            overall.finish();
+        }
 
-                        if let Some(file) = e.files.iter().find(|f| f.quality == quality) {
-                            let filename = Utils::generate_filename(
-                                item,
-                                &quality,
-                                Some(s.number),
-                                Some(e.number),
-                            )?;
+        if result.is_ok() && (options.write_nfo || options.write_poster) {
+            self.write_sidecars(item, &files, &output_dir, &options)
+                .await?;
+        }
 
-                            self.download_single_file(&filename, &file.url.http, &filename)
-                                .await?;
-                        }
+        // Per-file lines come from the downloader; a multi-file run also
+        // gets one totals line across the whole selection.
+        if result.is_ok() && files.len() > 1 {
+            let bytes = total_bytes.load(std::sync::atomic::Ordering::SeqCst);
+            let elapsed = started.elapsed();
+
+            println!(
+                "Downloaded {} files, {} in {:.1?} ({}/s)",
+                files.len(),
+                humansize::format_size(bytes, humansize::DECIMAL),
+                elapsed,
+                humansize::format_size(
+                    crate::parallel_downloader::average_rate(bytes, elapsed),
+                    humansize::DECIMAL
+                ),
+            );
+        }
+
+        result
+    }
+
+    /// Library metadata next to each downloaded video: a .nfo sidecar, the
+    /// poster image, or both. Subtitle files get no sidecars of their own.
+    async fn write_sidecars(
+        &self,
+        item: &Item,
+        files: &[ResolvedFile],
+        output_dir: &Path,
+        options: &DownloadOptions,
+    ) -> Result<()> {
+        let (info, rating) = item_metadata(item);
+
+        for file in files {
+            let path = output_dir.join(&file.relative_path);
+
+            if matches!(
+                path.extension().and_then(|extension| extension.to_str()),
+                Some("srt" | "vtt")
+            ) {
+                continue;
+            }
+
+            if options.write_nfo {
+                write_nfo(&path.with_extension("nfo"), info, rating)?;
+            }
+
+            if options.write_poster {
+                match info.posters.as_ref().and_then(|posters| posters.big.as_deref()) {
+                    Some(url) => {
+                        let client = self.config.http_client()?;
+                        let bytes = utils::send_traced(&client, client.get(url))
+                            .await?
+                            .error_for_status()?
+                            .bytes()
+                            .await?;
+                        std::fs::write(poster_path(&path), &bytes)?;
                     }
+                    None => log::warn!("no poster URL available for '{}'", info.title),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn download_single_file(
+        &self,
+        title: &str,
+        url: &str,
+        relative_path: &Path,
+        output_dir: &Path,
+        options: &DownloadOptions,
+        multi_progress: Option<Arc<MultiProgress>>,
+    ) -> Result<u64> {
+        let mut save_to = output_dir.join(relative_path);
+
+        // One configured client serves the probes below and the download
+        // itself, so proxy, timeout and User-Agent settings apply throughout.
+        let client = self.config.http_client()?;
+
+        // The resolver could only consult the URL; when that named no
+        // container the name fell back to .mp4, and the server may know
+        // better.
+        if utils::extension_from_url(url).is_none() && save_to.extension() == Some("mp4".as_ref()) {
+            if let Some(extension) = remote_extension(&client, url).await {
+                save_to.set_extension(extension);
+            }
+        }
+
+        if options.skip_existing {
+            // A failed probe must not abort the run: treat the file as not
+            // complete and let the download surface the real error, the same
+            // policy the pre-flight probes follow.
+            match remote_content_length(&client, url).await {
+                Ok(remote_size) if is_already_complete(&save_to, remote_size) => {
+                    log::info!("'{}': skipping, already complete", save_to.display());
+                    return Ok(0);
                 }
+                Ok(_) => {}
+                Err(err) => log::warn!(
+                    "could not determine the remote size of '{}' ({}); downloading",
+                    url,
+                    err
+                ),
+            }
+        }
+
+        clobber_check(&save_to, options.overwrite)?;
+
+        if let Some(parent) = save_to.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let bytes = Downloader::default()
+            .with_quiet(options.quiet)
+            .with_client(client)
+            .with_multi_progress(multi_progress)
+            .with_max_rate(options.max_rate)
+            .with_per_connection_rate(options.limit_rate_per_connection)
+            .with_chunk_size(options.chunk_size)
+            .with_byte_range(options.range.or(options.head.map(|limit| (0, limit))))
+            .with_retries(options.retries)
+            .with_idle_timeout(options.timeout_secs.map(std::time::Duration::from_secs))
+            .download_to(url, title, save_to.clone(), self.config.threads)
+            .await?;
+
+        if let Some(expected) = &options.sha256 {
+            verify_digest(&save_to, expected, HashAlgorithm::Sha256)?;
+        }
+
+        if let Some(expected) = &options.md5 {
+            verify_digest(&save_to, expected, HashAlgorithm::Md5)?;
+        }
+
+        Ok(bytes)
+    }
+
+    /// Compares the compiled version against the latest GitHub release and
+    /// reports either way. Returns whether an update exists, so the caller
+    /// can turn it into a script-friendly exit code.
+    pub async fn check_update(&self) -> Result<bool> {
+        let latest = latest_release_tag(&self.config.http_client()?).await?;
+        let current = env!("CARGO_PKG_VERSION");
+
+        if is_newer(&latest, current) {
+            println!("Update available: {} (you have {})", latest, current);
+            return Ok(true);
+        }
+
+        println!("You are up to date ({})", current);
+        Ok(false)
+    }
+
+    /// Runs every setup check, prints the checklist, and reports the overall
+    /// verdict. Always returns `Ok`: a failed check is the diagnosis, not an
+    /// error in the diagnosing.
+    pub async fn doctor(&self, config_dir: &Path, token_path: &Path) -> Result<()> {
+        let mut checks = vec![
+            ("config directory writable", check_dir_writable(config_dir)),
+            ("token file", check_token_file(token_path)),
+            ("download directory writable", check_dir_writable(&std::env::current_dir()?)),
+        ];
+
+        // Only an authenticated setup can prove API connectivity; without a
+        // token the check would always fail for the wrong reason.
+        let connectivity = if self.storage.get_data().is_some() {
+            match self.current_user().await {
+                Ok(user) => CheckOutcome::Pass(format!("logged in as '{}'", user.username)),
+                Err(err) => CheckOutcome::Fail(format!("API request failed: {}", err)),
             }
+        } else {
+            CheckOutcome::Fail("not authenticated; run the `authenticate` command".to_string())
+        };
+        checks.insert(2, ("API connectivity", connectivity));
+
+        let mut failures = 0;
+        for (name, outcome) in &checks {
+            let (mark, detail) = match outcome {
+                CheckOutcome::Pass(detail) => ("ok", detail),
+                CheckOutcome::Fail(detail) => {
+                    failures += 1;
+                    ("FAIL", detail)
+                }
+            };
+            println!("[{:>4}] {}: {}", mark, name, detail);
+        }
+
+        match failures {
+            0 => println!("Everything looks good."),
+            n => println!("{} of {} checks failed.", n, checks.len()),
         }
 
         Ok(())
     }
 
-    async fn download_single_file(&self, title: &str, url: &str, filename: &str) -> Result<()> {
-        let save_to = std::env::current_dir().unwrap().join(filename);
+    /// Search-then-download in one invocation: prints the numbered results,
+    /// asks which one to fetch (plus optional quality/season/episode) on
+    /// stdin, and hands the answers to the regular download path.
+    pub async fn interactive(&self, query: &str, quiet: bool) -> Result<()> {
+        use std::io::IsTerminal;
+
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "interactive mode needs a terminal on stdin; \
+                 use the `search` and `download` commands in scripts"
+            );
+        }
+
+        let results = self.search(query, None, None).await?;
+        if results.items.is_empty() {
+            bail!("no results for '{}'", query);
+        }
+
+        for (number, item) in results.items.iter().enumerate() {
+            println!(
+                "{:>3}. {} ({}) [{}]",
+                number + 1,
+                item.title,
+                item.year,
+                item.r#type
+            );
+        }
+
+        let choice = prompt_choice(
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout(),
+            results.items.len(),
+        )?;
+        let item = &results.items[choice.index - 1];
 
-        Downloader::default()
-            .download_to(url, title, save_to, self.config.threads)
-            .await
+        self.download(
+            item.id,
+            DownloadOptions {
+                quality: choice.quality,
+                season: choice.season,
+                episode: choice.episode,
+                quiet,
+                ..Default::default()
+            },
+        )
+        .await
     }
 
     async fn request<T: for<'de> Deserialize<'de>>(&self, api: Api<T>) -> Result<T> {
         let access_token = self.auth.authenticate().await?;
         self.api_client.set_access_token(&access_token);
-        self.api_client.get(api).await
+
+        match self.api_client.get(api.clone()).await {
+            // A locally fresh-looking token the server rejects anyway: force a
+            // refresh-token exchange and retry once, never more.
+            Err(err) if is_unauthorized(&err) => {
+                log::info!("access token rejected; refreshing and retrying once");
+
+                let access_token = self.auth.reauthenticate().await?;
+                self.api_client.set_access_token(&access_token);
+                self.api_client.get(api).await
+            }
+            result => result,
+        }
+    }
+}
+
+/// Latest release tag published on GitHub, e.g. "v1.4.0". Only queried when
+/// the update subcommand is invoked; nothing phones home on its own.
+async fn latest_release_tag(client: &reqwest::Client) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+
+    let release: Release = client
+        .get("https://api.github.com/repos/vstepanyuk/kinopub-downloader/releases/latest")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(release.tag_name)
+}
+
+/// Whether `latest` is a newer version than `current`. Tags may carry a `v`
+/// prefix; missing components count as zero, so "2.0" beats "1.9.9".
+fn is_newer(latest: &str, current: &str) -> bool {
+    fn parts(version: &str) -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    }
+
+    let (latest, current) = (parts(latest), parts(current));
+
+    for position in 0..latest.len().max(current.len()) {
+        let newer = latest.get(position).copied().unwrap_or(0);
+        let older = current.get(position).copied().unwrap_or(0);
+
+        if newer != older {
+            return newer > older;
+        }
+    }
+
+    false
+}
+
+fn is_unauthorized(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<ApiError>()
+        .map(|api_err| api_err.status == reqwest::StatusCode::UNAUTHORIZED)
+        .unwrap_or(false)
+}
+
+/// Runs the per-file download futures with at most `parallel` of them in
+/// flight at once; `1` reproduces the old strictly sequential behavior.
+async fn run_downloads<'a, F, Fut>(
+    files: &'a [ResolvedFile],
+    parallel: usize,
+    download: F,
+) -> Result<()>
+where
+    F: Fn(&'a ResolvedFile) -> Fut,
+    Fut: std::future::Future<Output = Result<()>> + 'a,
+{
+    let results: Vec<_> = futures::stream::iter(files)
+        .map(download)
+        .buffer_unordered(parallel.max(1))
+        .collect()
+        .await;
+
+    results.into_iter().collect()
+}
+
+/// Counts completed files against the whole selection, e.g. "4/24 files".
+/// The length is the resolved file count, so season and episode filters are
+/// already accounted for.
+fn overall_progress(
+    multi_progress: &MultiProgress,
+    total: usize,
+    known_bytes: u64,
+) -> ProgressBar {
+    let overall = multi_progress.add(ProgressBar::new(total as u64));
+    overall.set_style(ProgressStyle::default_bar().template("{msg} {pos}/{len} files"));
+
+    // The pre-flight probes already know the aggregate size, so show it.
+    overall.set_message(match known_bytes {
+        0 => "Overall".to_string(),
+        bytes => format!("Overall ({})", humansize::format_size(bytes, humansize::DECIMAL)),
+    });
+
+    overall
+}
+
+/// Compares the file's digest against the expected hex string, removing the
+/// file on a mismatch so a corrupt download is never mistaken for a good one.
+fn verify_digest(path: &Path, expected: &str, algorithm: HashAlgorithm) -> Result<()> {
+    let actual = crate::utils::file_digest(path, algorithm)?;
+
+    if actual.eq_ignore_ascii_case(expected) {
+        println!("'{}': checksum OK ({:?})", path.display(), algorithm);
+        return Ok(());
+    }
+
+    std::fs::remove_file(path)?;
+
+    bail!(
+        "'{}': {:?} mismatch (expected {}, got {}); corrupt file removed",
+        path.display(),
+        algorithm,
+        expected,
+        actual
+    )
+}
+
+/// Verdict of one `doctor` check, with a human-readable detail line.
+#[derive(Debug)]
+pub enum CheckOutcome {
+    Pass(String),
+    Fail(String),
+}
+
+/// Proves a directory is writable the only reliable way: by creating (and
+/// removing) a probe file in it.
+fn check_dir_writable(dir: &Path) -> CheckOutcome {
+    if !dir.is_dir() {
+        return CheckOutcome::Fail(format!("'{}' is not a directory", dir.display()));
+    }
+
+    let probe = dir.join(".kinopub-doctor-probe");
+    match std::fs::write(&probe, b"probe") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            CheckOutcome::Pass(format!("'{}'", dir.display()))
+        }
+        Err(err) => CheckOutcome::Fail(format!("cannot write to '{}': {}", dir.display(), err)),
+    }
+}
+
+/// Checks the token file exists and still parses as stored token data; it
+/// may be expired, the refresh flow handles that on the next request.
+fn check_token_file(path: &Path) -> CheckOutcome {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => {
+            return CheckOutcome::Fail(format!(
+                "no token file at '{}'; run the `authenticate` command",
+                path.display()
+            ))
+        }
+    };
+
+    match serde_json::from_str::<crate::auth::token::TokenData>(&contents) {
+        Ok(data) => CheckOutcome::Pass(match data.username {
+            Some(username) => format!("token for '{}'", username),
+            None => "token present".to_string(),
+        }),
+        Err(err) => CheckOutcome::Fail(format!(
+            "token file '{}' is corrupt: {}",
+            path.display(),
+            err
+        )),
+    }
+}
+
+/// Answers collected at the interactive prompt: a 1-based pick from the
+/// printed results plus the optional download filters.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct InteractiveChoice {
+    pub index: usize,
+    pub quality: Option<String>,
+    pub season: Option<EpisodeSelector>,
+    pub episode: Option<EpisodeSelector>,
+}
+
+/// Runs the whole selection dialog against any reader/writer pair, so tests
+/// can feed canned answers. Invalid picks re-prompt; an empty answer to an
+/// optional question keeps the default.
+pub(crate) fn prompt_choice<R: std::io::BufRead, W: std::io::Write>(
+    input: &mut R,
+    output: &mut W,
+    count: usize,
+) -> Result<InteractiveChoice> {
+    let index = loop {
+        let answer = prompt_line(input, output, &format!("Pick a result [1-{}]: ", count))?;
+        match answer.and_then(|answer| answer.parse::<usize>().ok()) {
+            Some(number) if (1..=count).contains(&number) => break number,
+            _ => writeln!(output, "Enter a number between 1 and {}.", count)?,
+        }
+    };
+
+    let quality = prompt_line(input, output, "Quality (Enter for best): ")?;
+    let season = prompt_selector(input, output, "Season (Enter for all): ")?;
+    let episode = prompt_selector(input, output, "Episode (Enter for all): ")?;
+
+    Ok(InteractiveChoice {
+        index,
+        quality,
+        season,
+        episode,
+    })
+}
+
+/// Writes `prompt`, reads one line, and returns it trimmed; `None` when the
+/// user just pressed Enter. A closed input mid-dialog is an error, not a
+/// default answer.
+fn prompt_line<R: std::io::BufRead, W: std::io::Write>(
+    input: &mut R,
+    output: &mut W,
+    prompt: &str,
+) -> Result<Option<String>> {
+    write!(output, "{}", prompt)?;
+    output.flush()?;
+
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        bail!("input closed before the prompt was answered");
+    }
+
+    let line = line.trim();
+    Ok((!line.is_empty()).then(|| line.to_string()))
+}
+
+/// One optional selector question; an answer that does not parse re-prompts
+/// with the parse error instead of aborting the dialog.
+fn prompt_selector<R: std::io::BufRead, W: std::io::Write>(
+    input: &mut R,
+    output: &mut W,
+    prompt: &str,
+) -> Result<Option<EpisodeSelector>> {
+    loop {
+        match prompt_line(input, output, prompt)? {
+            None => return Ok(None),
+            Some(answer) => match answer.parse() {
+                Ok(selector) => return Ok(Some(selector)),
+                Err(err) => writeln!(output, "{}", err)?,
+            },
+        }
+    }
+}
+
+/// Fails fast when the target filesystem cannot hold the whole selection,
+/// before the first byte is transferred.
+fn check_free_space(needed: u64, available: u64) -> Result<()> {
+    if needed > available {
+        bail!(
+            "not enough free space: need {}, have {} (use --no-space-check to download anyway)",
+            humansize::format_size(needed, humansize::DECIMAL),
+            humansize::format_size(available, humansize::DECIMAL)
+        );
+    }
+
+    Ok(())
+}
+
+/// Container extension the server names for `url` through `Content-Type` or
+/// `Content-Disposition`, for URLs whose path does not name one itself.
+async fn remote_extension(client: &reqwest::Client, url: &str) -> Option<String> {
+    let response = client.head(url).send().await.ok()?;
+
+    let header = |name: reqwest::header::HeaderName| {
+        response
+            .headers()
+            .get(&name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    };
+
+    utils::extension_from_headers(
+        header(reqwest::header::CONTENT_TYPE).as_deref(),
+        header(reqwest::header::CONTENT_DISPOSITION).as_deref(),
+    )
+}
+
+/// HEAD-probes every resolved file concurrently (bounded by `parallel`),
+/// returning one entry per file in the same order. A failed probe yields
+/// `None`: its size simply stays unknown, the download itself will surface
+/// the real error. Probes ride the configured client, so proxy, timeout and
+/// User-Agent settings apply to them like to every other request.
+async fn probe_files(
+    files: &[ResolvedFile],
+    parallel: usize,
+    client: reqwest::Client,
+) -> Vec<Option<HeadInfo>> {
+    let downloader = Downloader::default().with_client(client);
+
+    futures::stream::iter(files)
+        .map(|file| downloader.probe(&file.url))
+        .buffered(parallel.max(1))
+        .map(Result::ok)
+        .collect()
+        .await
+}
+
+/// Size the server reports for `url`, used to decide whether an existing
+/// file can be skipped.
+async fn remote_content_length(client: &reqwest::Client, url: &str) -> Result<u64> {
+    let response = client.head(url).send().await?;
+
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .ok_or_else(|| anyhow!("Failed to get content length from '{}'", url))?
+        .to_str()?
+        .parse::<u64>()
+        .map_err(Into::into)
+}
+
+/// An existing file counts as complete only when its size matches what the
+/// server reports; a mismatch means the previous run was interrupted.
+fn is_already_complete(path: &Path, remote_size: u64) -> bool {
+    match std::fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.len() == remote_size,
+        Err(_) => false,
+    }
+}
+
+/// Maps a 1-based absolute episode number ("episode 47 of the show") to the
+/// `(season, episode)` numbers it lands on, walking seasons in listed order.
+fn map_absolute_episode(seasons: &[SeriesSeason], absolute: usize) -> Result<(usize, usize)> {
+    if absolute == 0 {
+        bail!("absolute episode numbers start at 1");
+    }
+
+    let mut remaining = absolute;
+    for season in seasons {
+        if remaining <= season.episodes.len() {
+            return Ok((season.number, season.episodes[remaining - 1].number));
+        }
+        remaining -= season.episodes.len();
+    }
+
+    let total: usize = seasons.iter().map(|s| s.episodes.len()).sum();
+    bail!(
+        "absolute episode {} is out of range; the show has {} episodes",
+        absolute,
+        total
+    )
+}
+
+/// Canonicalizes a quality synonym ("4k", "fhd", "hd") before file
+/// selection. Aliases from the config file win over the built-in table;
+/// values neither knows pass through untouched.
+fn resolve_quality_alias(
+    quality: &str,
+    overrides: &std::collections::HashMap<String, String>,
+) -> String {
+    if let Some((_, canonical)) = overrides
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(quality))
+    {
+        return canonical.clone();
+    }
+
+    match quality.to_ascii_lowercase().as_str() {
+        "4k" | "uhd" => "2160p",
+        "fhd" => "1080p",
+        "hd" => "720p",
+        "sd" => "480p",
+        _ => return quality.to_owned(),
+    }
+    .to_owned()
+}
+
+/// Filename for one video file: the user's `--name-template` when given,
+/// otherwise the classic `Utils::generate_filename` layout. The container
+/// extension comes from the URL path when it names one, else `.mp4`; the
+/// download step may still correct it from the server's headers.
+fn resolved_filename(
+    item: &Item,
+    file: &MovieFile,
+    template: &Option<String>,
+    episode: Option<(&SeriesSeason, &crate::api::SeriesEpisode)>,
+    part: Option<usize>,
+) -> Result<String> {
+    let extension =
+        utils::extension_from_url(&file.url.http).unwrap_or_else(|| "mp4".to_owned());
+
+    let template = match template {
+        Some(template) => template,
+        None => {
+            return Utils::generate_filename(
+                item,
+                &file.quality,
+                episode.map(|(s, e)| (s.number, e.number)),
+                part,
+                &extension,
+            )
+        }
+    };
+
+    let ctx = utils::FilenameContext {
+        title: Utils::item_title(item),
+        year: Utils::item_year(item),
+        quality: file.quality.clone(),
+        season: episode.map(|(s, _)| s.number),
+        episode: episode.map(|(_, e)| e.number),
+        episode_title: episode.and_then(|(_, e)| {
+            let title = e.title.trim();
+            (!title.is_empty()).then(|| title.to_owned())
+        }),
+    };
+
+    let rendered = utils::render_template(template, &ctx)?;
+
+    // Templates have no part placeholder, so the suffix is appended; without
+    // it every part of a multi-part movie would render the same name.
+    Ok(match part {
+        Some(part) => format!("{} [Part {}].{}", rendered, part, extension),
+        None => format!("{}.{}", rendered, extension),
+    })
+}
+
+/// Failures `resolve_files` reports in a form callers can match on, instead
+/// of terminating the process; `anyhow` carries it up to `main`, which turns
+/// it into a nonzero exit like any other error.
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("File with {requested} quality is not found. Available: {}.", available.join(", "))]
+    QualityNotFound {
+        requested: String,
+        available: Vec<String>,
+    },
+}
+
+/// Expands the item and selection flags into the concrete list of files a
+/// download would fetch. Shared between the real download, --dry-run and
+/// other planning paths so they cannot disagree.
+fn resolve_files(item: &Item, options: &DownloadOptions) -> Result<Vec<ResolvedFile>> {
+    let quality = options
+        .quality
+        .clone()
+        .unwrap_or_else(|| "720p".to_owned());
+    let quality = resolve_quality_alias(&quality, &options.quality_aliases);
+
+    let mut files = vec![];
+
+    match item {
+        Item::Movie { videos, .. } | Item::Other { videos, .. } => {
+            let selected: Vec<(usize, &crate::api::Video)> = match options.part {
+                Some(part) => {
+                    let video = part
+                        .checked_sub(1)
+                        .and_then(|index| videos.get(index))
+                        .ok_or_else(|| {
+                            anyhow!("no part {}; this item has {} part(s)", part, videos.len())
+                        })?;
+                    vec![(part, video)]
+                }
+                None => videos.iter().enumerate().map(|(i, v)| (i + 1, v)).collect(),
+            };
+
+            // Part numbers only show up in names when there is more than one
+            // part to tell apart.
+            let multi_part = videos.len() > 1;
+
+            for (part_number, video) in selected {
+                let file = select_file(
+                    &video.files,
+                    &quality,
+                    options.fallback_quality,
+                    options.audio.as_deref(),
+                    options.lang.as_deref(),
+                    options.codec.as_deref(),
+                )
+                .ok_or_else(|| DownloadError::QualityNotFound {
+                    requested: quality.clone(),
+                    available: video.files.iter().map(|f| f.quality.clone()).collect(),
+                })?;
+
+                warn_on_fallback(&quality, file);
+
+                let filename = resolved_filename(
+                    item,
+                    file,
+                    &options.name_template,
+                    None,
+                    multi_part.then_some(part_number),
+                )?;
+
+                files.push(ResolvedFile {
+                    title: filename.clone(),
+                    url: file.url.http.clone(),
+                    relative_path: PathBuf::from(filename.clone()),
+                });
+
+                files.extend(resolve_subtitles(
+                    &video.subtitles,
+                    &options.subtitles,
+                    &PathBuf::from(filename),
+                ));
+            }
+        }
+        Item::Series { seasons, .. }
+        | Item::TvShow { seasons, .. }
+        | Item::DocSeries { seasons, .. } => {
+            let season_by_index =
+                use_positional(options, &options.season, seasons.iter().map(|s| s.number));
+
+            for (season_position, s) in seasons.iter().enumerate() {
+                let season_number = if season_by_index { season_position + 1 } else { s.number };
+                if let Some(selector) = &options.season {
+                    if !selector.matches(season_number) {
+                        continue;
+                    }
+                    if season_by_index {
+                        log::info!(
+                            "season at position {} selected (listed as number {})",
+                            season_number,
+                            s.number
+                        );
+                    }
+                }
+
+                if let Some(selector) = &options.exclude_season {
+                    if selector.matches(season_number) {
+                        continue;
+                    }
+                }
+
+                let episode_by_index =
+                    use_positional(options, &options.episode, s.episodes.iter().map(|e| e.number));
+
+                for (episode_position, e) in s.episodes.iter().enumerate() {
+                    let episode_number =
+                        if episode_by_index { episode_position + 1 } else { e.number };
+                    if let Some(selector) = &options.episode {
+                        if !selector.matches(episode_number) {
+                            continue;
+                        }
+                        if episode_by_index {
+                            log::info!(
+                                "episode at position {} selected (listed as number {})",
+                                episode_number,
+                                e.number
+                            );
+                        }
+                    }
+
+                    if let Some(selector) = &options.exclude_episode {
+                        if selector.matches(episode_number) {
+                            continue;
+                        }
+                    }
+
+                    let file = select_file(
+                        &e.files,
+                        &quality,
+                        options.fallback_quality,
+                        options.audio.as_deref(),
+                        options.lang.as_deref(),
+                        options.codec.as_deref(),
+                    );
+
+                    if let Some(file) = file {
+                        warn_on_fallback(&quality, file);
+
+                        let filename = resolved_filename(
+                            item,
+                            file,
+                            &options.name_template,
+                            Some((s, e)),
+                            None,
+                        )?;
+
+                        let relative_path = episode_relative_path(
+                            item,
+                            s.number,
+                            seasons.len(),
+                            &filename,
+                            options.flat,
+                        );
+
+                        files.extend(resolve_subtitles(
+                            &e.subtitles,
+                            &options.subtitles,
+                            &relative_path,
+                        ));
+
+                        files.push(ResolvedFile {
+                            title: filename.clone(),
+                            url: file.url.http.clone(),
+                            relative_path,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Writes the item back out in the API's own JSON shape, pretty-printed,
+/// so the dump can be inspected or fed to other tools.
+fn save_item_json(item: &Item, path: &Path) -> Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(item)?)?;
+    log::info!("item metadata saved to '{}'", path.display());
+
+    Ok(())
+}
+
+/// The shared metadata every item variant carries.
+fn item_metadata(item: &Item) -> (&crate::api::GeneralInfo, &crate::api::Rating) {
+    match item {
+        Item::Movie { info, rating, .. }
+        | Item::Other { info, rating, .. }
+        | Item::Series { info, rating, .. }
+        | Item::TvShow { info, rating, .. }
+        | Item::DocSeries { info, rating, .. } => (info, rating),
+    }
+}
+
+/// A minimal Kodi/Jellyfin .nfo document: title, year, plot, and whichever
+/// ratings the API reported.
+fn nfo_xml(info: &crate::api::GeneralInfo, rating: &crate::api::Rating) -> String {
+    let mut xml =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<movie>\n");
+
+    xml.push_str(&format!("  <title>{}</title>\n", xml_escape(&info.title)));
+    xml.push_str(&format!("  <year>{}</year>\n", info.year));
+    xml.push_str(&format!("  <plot>{}</plot>\n", xml_escape(&info.description)));
+
+    if let Some(imdb) = rating.imdb {
+        xml.push_str(&format!("  <rating name=\"imdb\">{:.1}</rating>\n", imdb));
+    }
+    if let Some(kinopoisk) = rating.kinopoisk {
+        xml.push_str(&format!(
+            "  <rating name=\"kinopoisk\">{:.1}</rating>\n",
+            kinopoisk
+        ));
+    }
+
+    xml.push_str("</movie>\n");
+    xml
+}
+
+fn write_nfo(path: &Path, info: &crate::api::GeneralInfo, rating: &crate::api::Rating) -> Result<()> {
+    std::fs::write(path, nfo_xml(info, rating))?;
+    Ok(())
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\"', "&quot;")
+}
+
+/// Kodi's `<name>-poster.jpg` convention, next to the video file.
+fn poster_path(video: &Path) -> PathBuf {
+    let mut name = video.file_stem().unwrap_or_default().to_os_string();
+    name.push("-poster.jpg");
+    video.with_file_name(name)
+}
+
+/// The no-clobber default: an existing target stops the download before a
+/// byte is transferred, since `download_to` would truncate it.
+fn clobber_check(save_to: &Path, overwrite: bool) -> Result<()> {
+    if save_to.exists() && !overwrite {
+        bail!(
+            "'{}' already exists; pass --overwrite to replace it or --skip-existing to keep complete files",
+            save_to.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Episodes already present under the output directory, keyed by the
+/// (season, episode) numbers parsed back out of the default file naming.
+/// Files renamed by hand or written with a custom --name-template are not
+/// recognized.
+fn existing_episodes(dir: &Path, item: &Item) -> std::collections::HashSet<(usize, usize)> {
+    fn scan(dir: &Path, title: &str, found: &mut std::collections::HashSet<(usize, usize)>) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            if path.is_dir() {
+                scan(&path, title, found);
+            } else if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                if name.starts_with(title) {
+                    if let Some(numbers) = season_episode_from_name(name) {
+                        found.insert(numbers);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut found = std::collections::HashSet::new();
+    scan(dir, &Utils::item_title(item), &mut found);
+    found
+}
+
+/// A resolved file survives --new-episodes when its name carries no episode
+/// numbers (movies, custom templates) or the numbers are not on disk yet.
+fn is_new_episode(
+    file: &ResolvedFile,
+    existing: &std::collections::HashSet<(usize, usize)>,
+) -> bool {
+    file.relative_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(season_episode_from_name)
+        .is_none_or(|numbers| !existing.contains(&numbers))
+}
+
+/// Parses "[Season: 02, Episode: 13 ...]" back out of a default-generated
+/// filename.
+fn season_episode_from_name(name: &str) -> Option<(usize, usize)> {
+    Some((
+        number_after(name, "Season: ")?,
+        number_after(name, "Episode: ")?,
+    ))
+}
+
+fn number_after(name: &str, label: &str) -> Option<usize> {
+    let rest = &name[name.find(label)? + label.len()..];
+    let digits: &str = &rest[..rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())];
+    digits.parse().ok()
+}
+
+/// Whether a selector should apply to 1-based positions instead of the
+/// API-reported `number`s: only under --by-index, and only when none of the
+/// listed numbers match it at all.
+fn use_positional(
+    options: &DownloadOptions,
+    selector: &Option<EpisodeSelector>,
+    mut numbers: impl Iterator<Item = usize>,
+) -> bool {
+    options.by_index
+        && selector
+            .as_ref()
+            .is_some_and(|selector| !numbers.any(|number| selector.matches(number)))
+}
+
+/// Subtitle downloads for one video: every available language, or just the
+/// requested one. They sit next to the video, sharing its base filename with
+/// the language and the track's own extension appended.
+fn resolve_subtitles(
+    subtitles: &[crate::api::Subtitle],
+    selection: &Option<Option<String>>,
+    video_path: &Path,
+) -> Vec<ResolvedFile> {
+    let language = match selection {
+        None => return vec![],
+        Some(language) => language.as_deref(),
+    };
+
+    subtitles
+        .iter()
+        .filter(|subtitle| language.is_none_or(|lang| subtitle.lang.eq_ignore_ascii_case(lang)))
+        .map(|subtitle| {
+            let extension = subtitle
+                .url
+                .rsplit('.')
+                .next()
+                .filter(|ext| matches!(*ext, "srt" | "vtt"))
+                .unwrap_or("srt");
+
+            let mut path = video_path.to_path_buf();
+            path.set_extension(format!("{}.{}", subtitle.lang, extension));
+
+            ResolvedFile {
+                title: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                url: subtitle.url.clone(),
+                relative_path: path,
+            }
+        })
+        .collect()
+}
+
+#[derive(Table)]
+struct PlanRow {
+    #[table(title = "File")]
+    file: String,
+    #[table(title = "Size", justify = "Justify::Right")]
+    size: String,
+    #[table(title = "URL")]
+    url: String,
+}
+
+/// Replaces the generated filename with the user's --output path, extension
+/// and all. Only meaningful when the selection is exactly one file.
+fn apply_output_override(files: &mut [ResolvedFile], output: &Path) -> Result<()> {
+    match files {
+        [file] => {
+            file.relative_path = output.to_path_buf();
+            file.title = output.display().to_string();
+            Ok(())
+        }
+        _ => bail!(
+            "--output only works for single-file downloads; this selection has {} files",
+            files.len()
+        ),
+    }
+}
+
+/// Lines for --print-url: the raw media URL per selected file, optionally
+/// with the filename tab-separated for scripts that want both columns.
+fn url_lines(files: &[ResolvedFile], with_name: bool) -> Vec<String> {
+    files
+        .iter()
+        .map(|file| {
+            if with_name {
+                format!("{}\t{}", file.url, file.relative_path.display())
+            } else {
+                file.url.clone()
+            }
+        })
+        .collect()
+}
+
+/// Prints what a download run would do: final paths, sizes (via HEAD
+/// requests) and source URLs.
+fn print_plan(files: &[ResolvedFile], probes: &[Option<HeadInfo>]) -> Result<()> {
+    let rows: Vec<_> = files
+        .iter()
+        .zip(probes)
+        .map(|(file, head)| PlanRow {
+            file: file.relative_path.display().to_string(),
+            size: head
+                .as_ref()
+                .map(|head| humansize::format_size(head.content_length, humansize::DECIMAL))
+                .unwrap_or_else(|| "?".to_string()),
+            url: file.url.clone(),
+        })
+        .collect();
+
+    print_stdout(rows.with_title())?;
+
+    Ok(())
+}
+
+#[derive(Table)]
+struct InfoRow {
+    #[table(title = "Season")]
+    scope: String,
+    #[table(title = "Episodes", justify = "Justify::Right")]
+    episodes: String,
+    #[table(title = "Duration", justify = "Justify::Right")]
+    duration: String,
+    #[table(title = "Qualities")]
+    qualities: String,
+}
+
+/// Header block for the info command: title, year, ratings and plot.
+fn render_info(item: &Item) -> String {
+    let (info, rating) = match item {
+        Item::Movie { info, rating, .. }
+        | Item::Series { info, rating, .. }
+        | Item::DocSeries { info, rating, .. }
+        | Item::TvShow { info, rating, .. }
+        | Item::Other { info, rating, .. } => (info, rating),
+    };
+
+    let format_rating = |rating: Option<f32>| match rating {
+        Some(rating) => format!("{:.1}", rating),
+        None => "-".to_string(),
+    };
+
+    format!(
+        "{} ({})\nIMDB: {}, Kinopoisk: {}\n\n{}",
+        Utils::item_title(item),
+        info.year,
+        format_rating(rating.imdb),
+        format_rating(rating.kinopoisk),
+        info.description
+    )
+}
+
+/// Table body for the info command: one row per video part for movies, one
+/// row per season for series.
+fn info_rows(item: &Item) -> Vec<InfoRow> {
+    match item {
+        Item::Movie { videos, .. } | Item::Other { videos, .. } => videos
+            .iter()
+            .map(|video| InfoRow {
+                scope: "-".to_string(),
+                episodes: "-".to_string(),
+                duration: render_duration(video.duration),
+                qualities: distinct_qualities(&video.files),
+            })
+            .collect(),
+        Item::Series { seasons, .. }
+        | Item::TvShow { seasons, .. }
+        | Item::DocSeries { seasons, .. } => seasons
+            .iter()
+            .map(|season| {
+                let files: Vec<_> = season
+                    .episodes
+                    .iter()
+                    .flat_map(|e| e.files.iter().cloned())
+                    .collect();
+
+                InfoRow {
+                    scope: season.number.to_string(),
+                    episodes: season.episodes.len().to_string(),
+                    duration: "-".to_string(),
+                    qualities: distinct_qualities(&files),
+                }
+            })
+            .collect(),
+    }
+}
+
+fn render_duration(seconds: u64) -> String {
+    format!("{}:{:02}:{:02}", seconds / 3600, (seconds % 3600) / 60, seconds % 60)
+}
+
+#[derive(Table)]
+struct QualityRow {
+    #[table(title = "Season", justify = "Justify::Right")]
+    season: String,
+    #[table(title = "Episode", justify = "Justify::Right")]
+    episode: String,
+    #[table(title = "Qualities")]
+    qualities: String,
+    #[table(title = "Audio tracks")]
+    audio: String,
+    #[table(title = "Codecs")]
+    codecs: String,
+}
+
+/// Prints the qualities an item offers: one row for a movie, one row per
+/// episode for series, since availability can differ between episodes.
+fn list_qualities(item: &Item) -> Result<()> {
+    let rows = match item {
+        Item::Movie { videos, .. } | Item::Other { videos, .. } => vec![QualityRow {
+            season: "-".to_string(),
+            episode: "-".to_string(),
+            qualities: videos
+                .first()
+                .map(|v| distinct_qualities_starred(&v.files))
+                .unwrap_or_default(),
+            audio: videos
+                .first()
+                .map(|v| distinct_audios(&v.files))
+                .unwrap_or_default(),
+            codecs: videos
+                .first()
+                .map(|v| distinct_codecs(&v.files))
+                .unwrap_or_default(),
+        }],
+        Item::Series { seasons, .. }
+        | Item::TvShow { seasons, .. }
+        | Item::DocSeries { seasons, .. } => seasons
+            .iter()
+            .flat_map(|s| {
+                s.episodes.iter().map(|e| QualityRow {
+                    season: s.number.to_string(),
+                    episode: e.number.to_string(),
+                    qualities: distinct_qualities_starred(&e.files),
+                    audio: distinct_audios(&e.files),
+                    codecs: distinct_codecs(&e.files),
+                })
+            })
+            .collect(),
+    };
+
+    print_stdout(rows.with_title())?;
+    println!("* what '--quality max' would pick");
+
+    Ok(())
+}
+
+/// Picks the file matching the requested quality. With `fallback` enabled,
+/// the next lower quality on the 2160p/1080p/720p/480p ladder is chosen when
+/// the exact one is missing, or the lowest higher one when nothing below the
+/// request exists.
+fn select_file<'a>(
+    files: &'a [MovieFile],
+    requested: &str,
+    fallback: bool,
+    audio: Option<&str>,
+    lang: Option<&str>,
+    codec: Option<&str>,
+) -> Option<&'a MovieFile> {
+    let audio_matched: Vec<&MovieFile> = files
+        .iter()
+        .filter(|file| matches_audio(file, audio))
+        .collect();
+
+    // Language is a preference like codec below: when no file carries the
+    // requested language, warn and keep the full candidate set.
+    let lang_matched: Vec<&MovieFile> = audio_matched
+        .iter()
+        .copied()
+        .filter(|file| matches_lang(file, lang))
+        .collect();
+
+    let audio_matched = if lang_matched.is_empty() {
+        if let Some(lang) = lang {
+            log::warn!("no file in language '{}'; ignoring the language preference", lang);
+        }
+        audio_matched
+    } else {
+        lang_matched
+    };
+
+    // Codec is a preference, not a hard requirement: when nothing carries the
+    // requested codec at all, fall back to the full candidate set rather than
+    // failing the download outright.
+    let codec_matched: Vec<&MovieFile> = audio_matched
+        .iter()
+        .copied()
+        .filter(|file| matches_codec(file, codec))
+        .collect();
+
+    let files = if codec_matched.is_empty() {
+        if let Some(codec) = codec {
+            log::warn!("no file with codec '{}'; ignoring the codec preference", codec);
+        }
+        audio_matched
+    } else {
+        codec_matched
+    };
+
+    // "max" picks the highest resolution on offer, which can differ per
+    // episode within the same show.
+    if requested.eq_ignore_ascii_case("max") {
+        return files
+            .iter()
+            .filter_map(|file| quality_height(&file.quality).map(|h| (h, *file)))
+            .max_by_key(|(height, _)| *height)
+            .map(|(_, file)| file);
+    }
+
+    if let Some(file) = files.iter().find(|f| f.quality == requested) {
+        return Some(file);
+    }
+
+    if !fallback {
+        return None;
+    }
+
+    let requested_height = quality_height(requested)?;
+    let ladder = |file: &&'a MovieFile| quality_height(&file.quality).map(|h| (h, *file));
+
+    files
+        .iter()
+        .filter_map(ladder)
+        .filter(|(height, _)| *height < requested_height)
+        .max_by_key(|(height, _)| *height)
+        .or_else(|| {
+            files
+                .iter()
+                .filter_map(ladder)
+                .filter(|(height, _)| *height > requested_height)
+                .min_by_key(|(height, _)| *height)
+        })
+        .map(|(_, file)| file)
+}
+
+/// Case-insensitive match on the file's codec; with no codec requested every
+/// file qualifies.
+fn matches_codec(file: &MovieFile, codec: Option<&str>) -> bool {
+    match codec {
+        None => true,
+        Some(requested) => file
+            .codec
+            .as_deref()
+            .is_some_and(|c| c.eq_ignore_ascii_case(requested)),
+    }
+}
+
+/// Case-insensitive match on the file's audio track name; with no track
+/// requested every file qualifies.
+fn matches_audio(file: &MovieFile, audio: Option<&str>) -> bool {
+    match audio {
+        None => true,
+        Some(requested) => file
+            .audio
+            .as_deref()
+            .is_some_and(|track| track.to_lowercase().contains(&requested.to_lowercase())),
+    }
+}
+
+/// Case-insensitive match on the file's ISO language code; with no language
+/// requested every file qualifies.
+fn matches_lang(file: &MovieFile, lang: Option<&str>) -> bool {
+    match lang {
+        None => true,
+        Some(requested) => file
+            .lang
+            .as_deref()
+            .is_some_and(|code| code.eq_ignore_ascii_case(requested)),
+    }
+}
+
+/// Numeric part of a quality label ("1080p" -> 1080).
+fn quality_height(quality: &str) -> Option<u32> {
+    quality
+        .trim_end_matches(|c: char| !c.is_ascii_digit())
+        .parse()
+        .ok()
+}
+
+fn warn_on_fallback(requested: &str, selected: &MovieFile) {
+    // "max" has no fixed target, so whatever was picked is not a fallback.
+    if requested.eq_ignore_ascii_case("max") {
+        return;
+    }
+
+    if selected.quality != requested {
+        log::warn!(
+            "{} unavailable, falling back to {}",
+            requested,
+            selected.quality
+        );
+    }
+}
+
+fn distinct_qualities(files: &[crate::api::MovieFile]) -> String {
+    let mut qualities: Vec<&str> = vec![];
+    for file in files {
+        if !qualities.contains(&file.quality.as_str()) {
+            qualities.push(&file.quality);
+        }
+    }
+
+    qualities.join(", ")
+}
+
+/// Like [`distinct_qualities`], but stars the entry `--quality max` would
+/// pick; used by `--list-qualities` only.
+fn distinct_qualities_starred(files: &[crate::api::MovieFile]) -> String {
+    let mut qualities: Vec<&str> = vec![];
+    for file in files {
+        if !qualities.contains(&file.quality.as_str()) {
+            qualities.push(&file.quality);
+        }
+    }
+
+    let best = qualities.iter().filter_map(|q| quality_height(q)).max();
+
+    qualities
+        .iter()
+        .map(|quality| {
+            if best.is_some() && quality_height(quality) == best {
+                format!("{}*", quality)
+            } else {
+                quality.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Distinct audio track names across the files, in first-seen order; "-"
+/// when the API reports none.
+fn distinct_audios(files: &[crate::api::MovieFile]) -> String {
+    let mut audios: Vec<&str> = vec![];
+    for file in files {
+        if let Some(audio) = file.audio.as_deref() {
+            if !audios.contains(&audio) {
+                audios.push(audio);
+            }
+        }
+    }
+
+    if audios.is_empty() {
+        "-".to_string()
+    } else {
+        audios.join(", ")
+    }
+}
+
+/// Distinct codecs across the files, in first-seen order; "-" when the API
+/// does not report them.
+fn distinct_codecs(files: &[crate::api::MovieFile]) -> String {
+    let mut codecs: Vec<&str> = vec![];
+    for file in files {
+        if let Some(codec) = file.codec.as_deref() {
+            if !codecs.contains(&codec) {
+                codecs.push(codec);
+            }
+        }
+    }
+
+    if codecs.is_empty() {
+        "-".to_string()
+    } else {
+        codecs.join(", ")
+    }
+}
+
+/// Relative path of an episode file below the output directory. The default
+/// layout is "<Series Title>/Season NN/<filename>", where the season number
+/// is zero-padded to the same width generate_filename uses; --flat keeps
+/// everything in the output directory itself.
+fn episode_relative_path(
+    item: &Item,
+    season_number: usize,
+    seasons_count: usize,
+    filename: &str,
+    flat: bool,
+) -> PathBuf {
+    if flat {
+        return PathBuf::from(filename);
+    }
+
+    let width = seasons_count.to_string().len();
+
+    PathBuf::from(Utils::item_title(item))
+        .join(format!("Season {:0width$}", season_number, width = width))
+        .join(filename)
+}
+
+/// Resolves the directory downloads are saved into, creating it when
+/// missing and defaulting to the current directory.
+fn resolve_output_dir(output_dir: Option<PathBuf>) -> Result<PathBuf> {
+    let dir = match output_dir {
+        Some(dir) => dir,
+        None => std::env::current_dir()?,
+    };
+
+    if dir.is_file() {
+        bail!("output directory '{}' is an existing file", dir.display());
+    }
+
+    std::fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        distinct_qualities, episode_relative_path, info_rows, is_already_complete, render_info,
+        resolve_files, resolve_output_dir, select_file, DownloadOptions,
+    };
+    use crate::api::Item;
+
+    pub(crate) fn series_fixture() -> Item {
+        serde_json::from_str(
+            r#"{"item": {
+                "type": "serial",
+                "title": "Сериал / The Series",
+                "year": 2020,
+                "plot": "A test series.",
+                "seasons": [
+                    {"title": "", "number": 1, "episodes": [
+                        {"title": "", "number": 1, "files": [
+                            {"quality": "720p", "url": {"http": "http://example.com/s1e1.mp4"}}
+                        ], "subtitles": [
+                            {"lang": "eng", "url": "http://example.com/s1e1.eng.srt"},
+                            {"lang": "rus", "url": "http://example.com/s1e1.rus.vtt"}
+                        ]}
+                    ]},
+                    {"title": "", "number": 2, "episodes": [
+                        {"title": "", "number": 1, "files": [
+                            {"quality": "720p", "url": {"http": "http://example.com/s2e1.mp4"}}
+                        ]}
+                    ]}
+                ]
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn nested_layout_uses_title_and_padded_season() {
+        let item = series_fixture();
+        let path = episode_relative_path(&item, 2, 2, "episode.mp4", false);
+
+        assert_eq!(
+            path,
+            std::path::Path::new("Сериал (The Series)")
+                .join("Season 2")
+                .join("episode.mp4")
+        );
+    }
+
+    #[test]
+    fn season_number_padding_follows_season_count() {
+        let item = series_fixture();
+        let path = episode_relative_path(&item, 2, 12, "episode.mp4", false);
+
+        assert!(path.to_string_lossy().contains("Season 02"));
+    }
+
+    #[test]
+    fn flat_layout_keeps_single_directory() {
+        let item = series_fixture();
+        let path = episode_relative_path(&item, 2, 2, "episode.mp4", true);
+
+        assert_eq!(path, std::path::PathBuf::from("episode.mp4"));
+    }
+
+    #[test]
+    fn distinct_qualities_dedupes_and_keeps_order() {
+        let files: Vec<crate::api::MovieFile> = serde_json::from_str(
+            r#"[
+                {"quality": "1080p", "url": {"http": "a"}},
+                {"quality": "720p", "url": {"http": "b"}},
+                {"quality": "1080p", "url": {"http": "c"}}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(distinct_qualities(&files), "1080p, 720p");
+        assert_eq!(super::distinct_qualities_starred(&files), "1080p*, 720p");
+    }
+
+    #[test]
+    fn max_quality_picks_the_highest_available() {
+        // Episodes of the same show can top out differently; "max" follows
+        // whatever each file list offers.
+        let episode_a = files(&["480p", "1080p", "720p"]);
+        let episode_b = files(&["480p", "720p"]);
+
+        assert_eq!(
+            select_file(&episode_a, "max", false, None, None, None).unwrap().quality,
+            "1080p"
+        );
+        assert_eq!(
+            select_file(&episode_b, "MAX", false, None, None, None).unwrap().quality,
+            "720p"
+        );
+        assert!(select_file(&[], "max", false, None, None, None).is_none());
+    }
+
+    fn files(qualities: &[&str]) -> Vec<crate::api::MovieFile> {
+        let json = qualities
+            .iter()
+            .map(|q| format!(r#"{{"quality": "{}", "url": {{"http": "http://example.com"}}}}"#, q))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        serde_json::from_str(&format!("[{}]", json)).unwrap()
+    }
+
+    fn movie_fixture() -> Item {
+        serde_json::from_str(
+            r#"{"item": {
+                "type": "movie",
+                "title": "Фильм / The Movie",
+                "year": 2021,
+                "plot": "A test movie.",
+                "imdb_rating": 7.8,
+                "videos": [
+                    {"duration": 5445, "files": [
+                        {"quality": "1080p", "url": {"http": "http://example.com/hd.mp4"}},
+                        {"quality": "720p", "url": {"http": "http://example.com/sd.mp4"}}
+                    ]}
+                ]
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn parallel_downloads_are_bounded_and_all_complete() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        use super::{run_downloads, ResolvedFile};
+
+        let files: Vec<_> = (0..6)
+            .map(|i| ResolvedFile {
+                title: format!("episode {}", i),
+                url: format!("http://example.com/{}.mp4", i),
+                relative_path: std::path::PathBuf::from(format!("{}.mp4", i)),
+            })
+            .collect();
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        run_downloads(&files, 2, |_| {
+            let in_flight = in_flight.clone();
+            let peak = peak.clone();
+            let completed = completed.clone();
+
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(current, Ordering::SeqCst);
+
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                completed.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(completed.load(Ordering::SeqCst), 6);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert!(peak.load(Ordering::SeqCst) >= 2, "downloads never overlapped");
+    }
+
+    #[test]
+    fn digest_verification_keeps_good_files_and_removes_bad_ones() {
+        use crate::utils::HashAlgorithm;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.mp4");
+        std::fs::write(&path, b"abc").unwrap();
+
+        super::verify_digest(
+            &path,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            HashAlgorithm::Sha256,
+        )
+        .unwrap();
+        assert!(path.exists());
+
+        let err = super::verify_digest(&path, "deadbeef", HashAlgorithm::Sha256).unwrap_err();
+        assert!(err.to_string().contains("mismatch"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn skips_only_files_with_the_expected_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("movie.mp4");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        assert!(is_already_complete(&path, 10));
+        assert!(!is_already_complete(&path, 11));
+        assert!(!is_already_complete(&dir.path().join("missing.mp4"), 10));
+    }
+
+    #[test]
+    fn info_renders_a_movie() {
+        let item = movie_fixture();
+
+        let header = render_info(&item);
+        assert!(header.contains("Фильм (The Movie) (2021)"));
+        assert!(header.contains("IMDB: 7.8, Kinopoisk: -"));
+        assert!(header.contains("A test movie."));
+
+        let rows = info_rows(&item);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].duration, "1:30:45");
+        assert_eq!(rows[0].qualities, "1080p, 720p");
+    }
+
+    #[test]
+    fn info_renders_a_series_breakdown() {
+        let item = series_fixture();
+
+        let header = render_info(&item);
+        assert!(header.contains("Сериал (The Series) (2020)"));
+
+        let rows = info_rows(&item);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].scope, "1");
+        assert_eq!(rows[0].episodes, "1");
+        assert_eq!(rows[0].qualities, "720p");
+    }
+
+    #[test]
+    fn resolve_files_expands_the_whole_series() {
+        let item = series_fixture();
+        let files = resolve_files(&item, &DownloadOptions::default()).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].url, "http://example.com/s1e1.mp4");
+        assert_eq!(files[1].url, "http://example.com/s2e1.mp4");
+    }
+
+    #[test]
+    fn subtitles_resolve_for_all_or_one_language() {
+        let item = series_fixture();
+
+        let all = resolve_files(
+            &item,
+            &DownloadOptions {
+                subtitles: Some(None),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap();
+
+        let subtitle_urls: Vec<_> = all
+            .iter()
+            .filter(|f| f.url.contains(".srt") || f.url.contains(".vtt"))
+            .map(|f| f.url.as_str())
+            .collect();
+        assert_eq!(
+            subtitle_urls,
+            vec![
+                "http://example.com/s1e1.eng.srt",
+                "http://example.com/s1e1.rus.vtt"
+            ]
+        );
+
+        // Subtitle files sit next to the episode, sharing its base name.
+        let episode = all.iter().find(|f| f.url.ends_with("s1e1.mp4")).unwrap();
+        let subtitle = all.iter().find(|f| f.url.ends_with(".rus.vtt")).unwrap();
+        assert_eq!(
+            subtitle.relative_path.parent(),
+            episode.relative_path.parent()
+        );
+        assert!(subtitle
+            .relative_path
+            .to_string_lossy()
+            .ends_with(".rus.vtt"));
+
+        let english_only = resolve_files(
+            &item,
+            &DownloadOptions {
+                subtitles: Some(Some("eng".to_string())),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(english_only.iter().any(|f| f.url.ends_with(".eng.srt")));
+        assert!(!english_only.iter().any(|f| f.url.ends_with(".rus.vtt")));
+
+        // Without the flag no subtitles are fetched at all.
+        let none = resolve_files(&item, &DownloadOptions::default()).unwrap();
+        assert!(!none.iter().any(|f| f.url.contains(".srt")));
+    }
+
+    fn two_part_movie_fixture() -> Item {
+        serde_json::from_str(
+            r#"{"item": {
+                "type": "movie",
+                "title": "Фильм / The Movie",
+                "year": 2021,
+                "plot": "A test movie.",
+                "videos": [
+                    {"duration": 5445, "files": [
+                        {"quality": "720p", "url": {"http": "http://example.com/part1.mp4"}}
+                    ]},
+                    {"duration": 5101, "files": [
+                        {"quality": "720p", "url": {"http": "http://example.com/part2.mp4"}}
+                    ]}
+                ]
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn multi_part_movies_resolve_every_part_with_distinct_names() {
+        let item = two_part_movie_fixture();
+
+        let files = resolve_files(&item, &DownloadOptions::default()).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].url, "http://example.com/part1.mp4");
+        assert_eq!(files[1].url, "http://example.com/part2.mp4");
+        assert_eq!(files[0].title, "Фильм (The Movie) [Part 1] [720p].mp4");
+        assert_eq!(files[1].title, "Фильм (The Movie) [Part 2] [720p].mp4");
+
+        // --part picks a single 1-based entry.
+        let files = resolve_files(
+            &item,
+            &DownloadOptions {
+                part: Some(2),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].url, "http://example.com/part2.mp4");
+
+        let err = resolve_files(
+            &item,
+            &DownloadOptions {
+                part: Some(3),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("no part 3"));
+
+        // A single-video movie keeps its unsuffixed name.
+        let files = resolve_files(&movie_fixture(), &DownloadOptions::default()).unwrap();
+        assert_eq!(files[0].title, "Фильм (The Movie) [720p].mp4");
+    }
+
+    #[test]
+    fn a_missing_quality_is_a_typed_error_not_an_exit() {
+        let item = movie_fixture();
+
+        let err = resolve_files(
+            &item,
+            &DownloadOptions {
+                // "480p" is not an alias, so it reaches the lookup as-is.
+                quality: Some("480p".to_string()),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap_err();
+
+        match err.downcast_ref::<super::DownloadError>() {
+            Some(super::DownloadError::QualityNotFound {
+                requested,
+                available,
+            }) => {
+                assert_eq!(requested, "480p");
+                assert_eq!(available, &["1080p", "720p"]);
+            }
+            None => panic!("expected DownloadError::QualityNotFound, got: {}", err),
+        }
+
+        assert!(err.to_string().contains("480p"));
+        assert!(err.to_string().contains("1080p, 720p"));
+    }
+
+    #[test]
+    fn resolve_files_honors_season_and_episode_filters() {
+        let item = series_fixture();
+
+        let options = DownloadOptions {
+            season: Some("2".parse().unwrap()),
+            ..DownloadOptions::default()
+        };
+        let files = resolve_files(&item, &options).unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].url, "http://example.com/s2e1.mp4");
+    }
+
+    #[test]
+    fn exclusions_trim_the_included_selection() {
+        let item: Item = serde_json::from_str(
+            r#"{"item": {
+                "type": "serial",
+                "title": "Сериал / The Series",
+                "year": 2020,
+                "plot": "A test series.",
+                "seasons": [
+                    {"title": "", "number": 1, "episodes": [
+                        {"title": "", "number": 1, "files": [{"quality": "720p", "url": {"http": "http://example.com/s1e1.mp4"}}]},
+                        {"title": "", "number": 2, "files": [{"quality": "720p", "url": {"http": "http://example.com/s1e2.mp4"}}]},
+                        {"title": "", "number": 3, "files": [{"quality": "720p", "url": {"http": "http://example.com/s1e3.mp4"}}]},
+                        {"title": "", "number": 4, "files": [{"quality": "720p", "url": {"http": "http://example.com/s1e4.mp4"}}]},
+                        {"title": "", "number": 5, "files": [{"quality": "720p", "url": {"http": "http://example.com/s1e5.mp4"}}]}
+                    ]},
+                    {"title": "", "number": 2, "episodes": [
+                        {"title": "", "number": 1, "files": [{"quality": "720p", "url": {"http": "http://example.com/s2e1.mp4"}}]}
+                    ]}
+                ]
+            }}"#,
+        )
+        .unwrap();
+
+        // --season 1 --exclude-episode 3,5
+        let files = resolve_files(
+            &item,
+            &DownloadOptions {
+                season: Some("1".parse().unwrap()),
+                exclude_episode: Some("3,5".parse().unwrap()),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap();
+
+        let urls: Vec<_> = files.iter().map(|f| f.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "http://example.com/s1e1.mp4",
+                "http://example.com/s1e2.mp4",
+                "http://example.com/s1e4.mp4",
+            ]
+        );
+
+        // Exclusion wins even when the same number is explicitly included.
+        let files = resolve_files(
+            &item,
+            &DownloadOptions {
+                episode: Some("3".parse().unwrap()),
+                exclude_episode: Some("3".parse().unwrap()),
+                exclude_season: Some("2".parse().unwrap()),
+                ..DownloadOptions::default()
+            },
+        )
+        .unwrap();
+        assert!(files.is_empty());
+    }
+
+    fn gapped_series_fixture() -> Item {
+        serde_json::from_str(
+            r#"{"item": {
+                "type": "serial",
+                "title": "Сериал / The Series",
+                "year": 2020,
+                "plot": "A test series.",
+                "seasons": [
+                    {"title": "", "number": 1, "episodes": [
+                        {"title": "", "number": 0, "files": [
+                            {"quality": "720p", "url": {"http": "http://example.com/s1e1.mp4"}}
+                        ]},
+                        {"title": "", "number": 1, "files": [
+                            {"quality": "720p", "url": {"http": "http://example.com/s1e2.mp4"}}
+                        ]}
+                    ]},
+                    {"title": "", "number": 3, "episodes": [
+                        {"title": "", "number": 0, "files": [
+                            {"quality": "720p", "url": {"http": "http://example.com/s3e1.mp4"}}
+                        ]}
+                    ]}
+                ]
+            }}"#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_fake_media_source_feeds_the_downloader() {
+        use super::{MediaSource, ResolvedFile};
+        use crate::parallel_downloader::Downloader;
+        use crate::test_util::FileServer;
+
+        struct FakeSource {
+            base_url: String,
+        }
+
+        impl MediaSource for FakeSource {
+            fn resolve_files(&self, _options: &DownloadOptions) -> anyhow::Result<Vec<ResolvedFile>> {
+                Ok(["one.bin", "two.bin"]
+                    .into_iter()
+                    .map(|name| ResolvedFile {
+                        title: name.to_owned(),
+                        url: self.base_url.clone(),
+                        relative_path: name.into(),
+                    })
+                    .collect())
+            }
+        }
+
+        let content: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), false).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let source = FakeSource {
+            base_url: server.url.clone(),
+        };
+        let files = source.resolve_files(&DownloadOptions::default()).unwrap();
+        assert_eq!(files.len(), 2);
+
+        for file in &files {
+            Downloader::default()
+                .download_to(&file.url, &file.title, dir.path().join(&file.relative_path), 2)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(std::fs::read(dir.path().join("one.bin")).unwrap(), content);
+        assert_eq!(std::fs::read(dir.path().join("two.bin")).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn end_to_end_device_flow_and_download_against_the_mock_server() {
+        use crate::auth::storage::JsonTokenStorage;
+        use crate::test_util::MockKinopub;
+
+        let server = MockKinopub::start().await;
+        let config = server.config();
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+        let app = super::App::new(&config, &storage);
+
+        // Empty storage forces the full device flow against the mock.
+        let user = app.current_user().await.unwrap();
+        assert_eq!(user.username, "mockuser");
+
+        let results = app.search("mock", None, None).await.unwrap();
+        assert_eq!(results.items.len(), 1);
+        assert_eq!(results.items[0].title, "Mock Movie");
+
+        app.download(
+            1,
+            DownloadOptions {
+                no_space_check: true,
+                output_dir: Some(dir.path().join("media")),
+                quiet: true,
+                ..DownloadOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let saved = dir.path().join("media").join("Mock Movie [720p].mp4");
+        assert_eq!(std::fs::read(saved).unwrap(), server.media_content);
+
+        let requests = server.requests();
+        assert!(requests.iter().any(|line| line.starts_with("POST /oauth2/device")));
+        assert!(requests.iter().any(|line| line.starts_with("GET /v1/items/1")));
+    }
+
+    #[tokio::test]
+    async fn an_env_access_token_never_triggers_the_device_flow() {
+        use crate::auth::storage::EnvTokenStorage;
+        use crate::test_util::MockKinopub;
+
+        let server = MockKinopub::start().await;
+        let config = server.config();
+
+        // Constructed directly rather than via the environment: the storage
+        // unit tests own `KINOPUB_ACCESS_TOKEN`, and two tests mutating the
+        // same process-global variable would race under the parallel harness.
+        let storage = EnvTokenStorage::new("ci-token");
+
+        let app = super::App::new(&config, &storage);
+        let user = app.current_user().await.unwrap();
+        assert_eq!(user.username, "mockuser");
+
+        let requests = server.requests();
+        assert!(!requests.iter().any(|line| line.starts_with("POST /oauth2")));
+    }
+
+    #[test]
+    fn omitted_selectors_still_name_each_fetched_episode() {
+        let item = series_fixture();
+
+        // No --season/--episode: every episode downloads, and each filename
+        // carries the numbers of the episode actually fetched.
+        let files = resolve_files(&item, &DownloadOptions::default()).unwrap();
+
+        assert!(files[0].title.contains("[Season: 1, Episode: 1]"));
+        assert!(files[1].title.contains("[Season: 2, Episode: 1]"));
+    }
+
+    #[test]
+    fn a_series_without_a_concrete_episode_gets_the_movie_style_name() {
+        let item = series_fixture();
+
+        assert_eq!(
+            crate::utils::Utils::generate_filename(&item, "720p", None, None, "mp4").unwrap(),
+            "Сериал (The Series) [720p].mp4"
+        );
+    }
+
+    #[test]
+    fn nfo_output_matches_the_fixture_metadata() {
+        let item = movie_fixture();
+        let (info, rating) = super::item_metadata(&item);
+
+        assert_eq!(
+            super::nfo_xml(info, rating),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <movie>\n  <title>Фильм / The Movie</title>\n  <year>2021</year>\n\
+             \x20 <plot>A test movie.</plot>\n  <rating name=\"imdb\">7.8</rating>\n</movie>\n"
+        );
+    }
+
+    #[test]
+    fn nfo_escapes_xml_metacharacters() {
+        assert_eq!(
+            super::xml_escape(r#"Fast & "Furious" <3>"#),
+            "Fast &amp; &quot;Furious&quot; &lt;3&gt;"
+        );
+    }
+
+    #[test]
+    fn poster_sits_next_to_the_video_with_the_kodi_suffix() {
+        assert_eq!(
+            super::poster_path(Path::new("Show/Season 1/episode.mp4")),
+            PathBuf::from("Show/Season 1/episode-poster.jpg")
+        );
+    }
+
+    #[test]
+    fn clobber_policy_guards_existing_files_only() {
+        use super::clobber_check;
+
+        let dir = tempfile::tempdir().unwrap();
+        let taken = dir.path().join("movie.mp4");
+        std::fs::write(&taken, b"finished download").unwrap();
+        let free = dir.path().join("new.mp4");
+
+        // Default no-clobber: an existing target is an error, a fresh one is
+        // fine.
+        let err = clobber_check(&taken, false).unwrap_err();
+        assert!(err.to_string().contains("--overwrite"));
+        assert!(clobber_check(&free, false).is_ok());
+
+        // --overwrite allows both.
+        assert!(clobber_check(&taken, true).is_ok());
+        assert!(clobber_check(&free, true).is_ok());
+
+        // The refused file is untouched.
+        assert_eq!(std::fs::read(&taken).unwrap(), b"finished download");
+    }
+
+    #[test]
+    fn new_episodes_skips_what_is_already_on_disk() {
+        let item = series_fixture();
+        let files = resolve_files(&item, &DownloadOptions::default()).unwrap();
+        assert_eq!(files.len(), 2);
+
+        // Pre-populate season 1 episode 1 in the nested layout.
+        let dir = tempfile::tempdir().unwrap();
+        let on_disk = dir.path().join(&files[0].relative_path);
+        std::fs::create_dir_all(on_disk.parent().unwrap()).unwrap();
+        std::fs::write(&on_disk, b"x").unwrap();
+
+        let existing = super::existing_episodes(dir.path(), &item);
+        assert_eq!(existing, [(1, 1)].into_iter().collect());
+
+        let remaining: Vec<_> = files
+            .into_iter()
+            .filter(|file| super::is_new_episode(file, &existing))
+            .collect();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].url.ends_with("s2e1.mp4"));
+    }
+
+    #[test]
+    fn by_index_falls_back_to_positions_when_numbers_have_gaps() {
+        let item = gapped_series_fixture();
+
+        // Season 2 does not exist by number; positionally it is the second
+        // entry, the one listed as number 3.
+        let options = DownloadOptions {
+            season: Some("2".parse().unwrap()),
+            by_index: true,
+            ..DownloadOptions::default()
+        };
+        let files = resolve_files(&item, &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].url, "http://example.com/s3e1.mp4");
+
+        // Zero-based episode listing: "episode 2" matches nothing by number,
+        // so it becomes the second entry of the season.
+        let options = DownloadOptions {
+            season: Some("1".parse().unwrap()),
+            episode: Some("2".parse().unwrap()),
+            by_index: true,
+            ..DownloadOptions::default()
+        };
+        let files = resolve_files(&item, &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].url, "http://example.com/s1e2.mp4");
+    }
+
+    #[test]
+    fn by_index_never_overrides_a_matching_number() {
+        let item = gapped_series_fixture();
+
+        // Season 3 exists by number, so the positional fallback stays out of
+        // the way even with the flag set.
+        let options = DownloadOptions {
+            season: Some("3".parse().unwrap()),
+            by_index: true,
+            ..DownloadOptions::default()
+        };
+        let files = resolve_files(&item, &options).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].url, "http://example.com/s3e1.mp4");
+
+        // Without the flag a gapped selection simply resolves to nothing.
+        let options = DownloadOptions {
+            season: Some("2".parse().unwrap()),
+            ..DownloadOptions::default()
+        };
+        assert!(resolve_files(&item, &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn output_overrides_the_single_resolved_filename() {
+        let item = series_fixture();
+
+        let options = DownloadOptions {
+            season: Some("2".parse().unwrap()),
+            ..DownloadOptions::default()
+        };
+        let mut files = resolve_files(&item, &options).unwrap();
+        assert_eq!(files.len(), 1);
+
+        super::apply_output_override(&mut files, Path::new("finale.mkv")).unwrap();
+
+        assert_eq!(files[0].relative_path, PathBuf::from("finale.mkv"));
+        assert_eq!(files[0].title, "finale.mkv");
+    }
+
+    #[test]
+    fn output_is_rejected_for_multi_file_selections() {
+        let item = series_fixture();
+
+        let mut files = resolve_files(&item, &DownloadOptions::default()).unwrap();
+        assert!(files.len() > 1);
+
+        let err = super::apply_output_override(&mut files, Path::new("finale.mkv")).unwrap_err();
+        assert!(err.to_string().contains("single-file"));
+    }
+
+    #[test]
+    fn update_check_compares_version_pairs() {
+        assert!(super::is_newer("v1.2.0", "1.1.9"));
+        assert!(super::is_newer("1.10.0", "1.9.0"));
+        assert!(super::is_newer("2.0", "1.9.9"));
+        assert!(!super::is_newer("v1.2.0", "1.2.0"));
+        assert!(!super::is_newer("1.2.0", "1.3.0"));
+        assert!(!super::is_newer("1.2", "1.2.1"));
+    }
+
+    #[test]
+    fn quality_aliases_map_to_canonical_values() {
+        let overrides = std::collections::HashMap::from([
+            ("best".to_string(), "2160p".to_string()),
+            // A user override beats the built-in meaning of "hd".
+            ("hd".to_string(), "1080p".to_string()),
+        ]);
+
+        assert_eq!(super::resolve_quality_alias("4K", &overrides), "2160p");
+        assert_eq!(super::resolve_quality_alias("fhd", &overrides), "1080p");
+        assert_eq!(super::resolve_quality_alias("HD", &overrides), "1080p");
+        assert_eq!(super::resolve_quality_alias("best", &overrides), "2160p");
+
+        let none = std::collections::HashMap::new();
+        assert_eq!(super::resolve_quality_alias("hd", &none), "720p");
+        assert_eq!(super::resolve_quality_alias("1080p", &none), "1080p");
+        assert_eq!(super::resolve_quality_alias("weird", &none), "weird");
+    }
+
+    #[test]
+    fn print_url_lines_match_the_selected_files() {
+        let item = series_fixture();
+        let options = DownloadOptions {
+            season: Some("2".parse().unwrap()),
+            ..DownloadOptions::default()
+        };
+        let files = resolve_files(&item, &options).unwrap();
+
+        let urls = super::url_lines(&files, false);
+        assert_eq!(urls, vec!["http://example.com/s2e1.mp4".to_string()]);
+
+        let with_names = super::url_lines(&files, true);
+        assert_eq!(with_names.len(), files.len());
+        assert!(with_names[0].starts_with("http://example.com/s2e1.mp4\t"));
+        assert!(with_names[0].ends_with(&files[0].relative_path.display().to_string()));
+    }
+
+    #[tokio::test]
+    async fn preflight_probes_every_file_once_and_sums_the_sizes() {
+        use crate::test_util::FileServer;
+
+        let servers = [
+            FileServer::start(vec![1u8; 10_000], false).await,
+            FileServer::start(vec![2u8; 20_000], false).await,
+            FileServer::start(vec![3u8; 30_000], false).await,
+        ];
+
+        let files: Vec<_> = servers
+            .iter()
+            .enumerate()
+            .map(|(i, server)| super::ResolvedFile {
+                title: format!("file {}", i),
+                url: server.url.clone(),
+                relative_path: PathBuf::from(format!("{}.bin", i)),
+            })
+            .collect();
+
+        let client = crate::api::Config::default().http_client().unwrap();
+        let probes = super::probe_files(&files, 2, client).await;
+
+        let sizes: Vec<_> = probes
+            .iter()
+            .map(|head| head.as_ref().unwrap().content_length)
+            .collect();
+        assert_eq!(sizes, vec![10_000, 20_000, 30_000]);
+        assert_eq!(sizes.iter().sum::<u64>(), 60_000);
+
+        for server in &servers {
+            assert_eq!(server.head_requests(), 1);
+        }
+    }
+
+    #[test]
+    fn overall_progress_total_matches_the_resolved_selection() {
+        let item = series_fixture();
+        let options = DownloadOptions {
+            season: Some("2".parse().unwrap()),
+            ..DownloadOptions::default()
+        };
+        let files = resolve_files(&item, &options).unwrap();
+
+        let multi = super::MultiProgress::new();
+        let overall = super::overall_progress(&multi, files.len(), 0);
+
+        assert_eq!(overall.length(), files.len() as u64);
+        assert_eq!(overall.length(), 1);
+    }
+
+    #[test]
+    fn resolve_files_skips_episodes_without_the_quality() {
+        let item = series_fixture();
+
+        let options = DownloadOptions {
+            quality: Some("2160p".to_string()),
+            ..DownloadOptions::default()
+        };
+
+        assert!(resolve_files(&item, &options).unwrap().is_empty());
+    }
+
+    fn files_with_audio(specs: &[(&str, &str)]) -> Vec<crate::api::MovieFile> {
+        let json = specs
+            .iter()
+            .map(|(quality, audio)| {
+                format!(
+                    r#"{{"quality": "{}", "audio": "{}", "url": {{"http": "http://example.com"}}}}"#,
+                    quality, audio
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        serde_json::from_str(&format!("[{}]", json)).unwrap()
+    }
+
+    #[test]
+    fn select_file_filters_by_audio_track() {
+        let files = files_with_audio(&[("1080p", "LostFilm"), ("1080p", "Original")]);
+
+        let selected = select_file(&files, "1080p", false, Some("lostfilm"), None, None).unwrap();
+        assert_eq!(selected.audio.as_deref(), Some("LostFilm"));
+
+        // A missing track yields nothing even if the quality exists.
+        assert!(select_file(&files, "1080p", false, Some("Kubik"), None, None).is_none());
+
+        // Quality fallback still applies within the chosen track.
+        let files = files_with_audio(&[("720p", "LostFilm"), ("1080p", "Original")]);
+        let selected = select_file(&files, "1080p", true, Some("LostFilm"), None, None).unwrap();
+        assert_eq!(selected.quality, "720p");
+    }
+
+    fn files_with_codec(specs: &[(&str, &str)]) -> Vec<crate::api::MovieFile> {
+        let json = specs
+            .iter()
+            .map(|(quality, codec)| {
+                format!(
+                    r#"{{"quality": "{}", "codec": "{}", "url": {{"http": "http://example.com"}}}}"#,
+                    quality, codec
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        serde_json::from_str(&format!("[{}]", json)).unwrap()
+    }
+
+    fn files_with_lang(specs: &[(&str, &str)]) -> Vec<crate::api::MovieFile> {
+        let json = specs
+            .iter()
+            .map(|(quality, lang)| {
+                format!(
+                    r#"{{"quality": "{}", "lang": "{}", "url": {{"http": "http://example.com/{}"}}}}"#,
+                    quality, lang, lang
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        serde_json::from_str(&format!("[{}]", json)).unwrap()
+    }
+
+    #[test]
+    fn select_file_prefers_the_requested_language() {
+        let files = files_with_lang(&[("1080p", "ru"), ("1080p", "en"), ("1080p", "uk")]);
+
+        let selected = select_file(&files, "1080p", false, None, Some("en"), None).unwrap();
+        assert_eq!(selected.lang.as_deref(), Some("en"));
+
+        // Codes match case-insensitively.
+        let selected = select_file(&files, "1080p", false, None, Some("UK"), None).unwrap();
+        assert_eq!(selected.lang.as_deref(), Some("uk"));
+
+        // An unavailable language is a preference only: the quality match
+        // still wins over failing the download.
+        let selected = select_file(&files, "1080p", false, None, Some("de"), None).unwrap();
+        assert_eq!(selected.quality, "1080p");
+
+        // Quality fallback applies within the chosen language.
+        let files = files_with_lang(&[("720p", "ru"), ("1080p", "en")]);
+        let selected = select_file(&files, "1080p", true, None, Some("ru"), None).unwrap();
+        assert_eq!(selected.quality, "720p");
+    }
+
+    #[test]
+    fn select_file_prefers_the_requested_codec() {
+        let files = files_with_codec(&[("1080p", "h264"), ("1080p", "hevc")]);
+
+        let selected = select_file(&files, "1080p", false, None, None, Some("hevc")).unwrap();
+        assert_eq!(selected.codec.as_deref(), Some("hevc"));
+    }
+
+    #[test]
+    fn missing_codec_falls_back_to_any_codec() {
+        let files = files_with_codec(&[("1080p", "h264"), ("720p", "h264")]);
+
+        // The preference cannot be met, so quality selection proceeds over
+        // the full set instead of failing.
+        let selected = select_file(&files, "1080p", false, None, None, Some("av1")).unwrap();
+        assert_eq!(selected.quality, "1080p");
+        assert_eq!(selected.codec.as_deref(), Some("h264"));
+    }
+
+    #[test]
+    fn distinct_codecs_lists_codecs_or_a_dash() {
+        let files = files_with_codec(&[("1080p", "h264"), ("720p", "hevc")]);
+        assert_eq!(super::distinct_codecs(&files), "h264, hevc");
+
+        let files = super::tests::files(&["1080p"]);
+        assert_eq!(super::distinct_codecs(&files), "-");
+    }
+
+    #[test]
+    fn distinct_audios_lists_tracks_or_a_dash() {
+        let files = files_with_audio(&[("1080p", "LostFilm"), ("720p", "LostFilm")]);
+        assert_eq!(super::distinct_audios(&files), "LostFilm");
+
+        let files = super::tests::files(&["1080p"]);
+        assert_eq!(super::distinct_audios(&files), "-");
+    }
+
+    #[test]
+    fn select_file_prefers_exact_quality() {
+        let files = files(&["2160p", "1080p", "720p"]);
+        assert_eq!(select_file(&files, "1080p", true, None, None, None).unwrap().quality, "1080p");
+    }
+
+    #[test]
+    fn select_file_without_fallback_requires_exact_match() {
+        let files = files(&["2160p", "480p"]);
+        assert!(select_file(&files, "1080p", false, None, None, None).is_none());
+    }
+
+    #[test]
+    fn select_file_falls_back_to_next_lower_quality() {
+        let files = files(&["2160p", "480p"]);
+        assert_eq!(select_file(&files, "1080p", true, None, None, None).unwrap().quality, "480p");
+    }
+
+    #[test]
+    fn select_file_falls_back_upwards_when_nothing_lower_exists() {
+        let files = files(&["2160p", "1080p"]);
+        assert_eq!(select_file(&files, "720p", true, None, None, None).unwrap().quality, "1080p");
+    }
+
+    #[test]
+    fn select_file_handles_empty_file_list() {
+        assert!(select_file(&[], "720p", true, None, None, None).is_none());
+    }
+
+    #[test]
+    fn defaults_to_current_directory() {
+        let dir = resolve_output_dir(None).unwrap();
+        assert_eq!(dir, std::env::current_dir().unwrap());
+    }
+
+    #[test]
+    fn creates_missing_directories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let nested = tmp.path().join("shows").join("season-1");
+
+        let dir = resolve_output_dir(Some(nested.clone())).unwrap();
+
+        assert_eq!(dir, nested);
+        assert!(nested.is_dir());
+    }
+
+    #[test]
+    fn rejects_a_path_that_is_a_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("taken");
+        std::fs::write(&file, b"x").unwrap();
+
+        assert!(resolve_output_dir(Some(file)).is_err());
+    }
+
+    #[test]
+    fn doctor_directory_check_probes_for_real_writability() {
+        use super::{check_dir_writable, CheckOutcome};
+
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            check_dir_writable(dir.path()),
+            CheckOutcome::Pass(_)
+        ));
+        // The probe file must not be left behind.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let missing = dir.path().join("nope");
+        match check_dir_writable(&missing) {
+            CheckOutcome::Fail(detail) => assert!(detail.contains("not a directory")),
+            other => panic!("expected failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn doctor_token_check_reports_missing_corrupt_and_valid_files() {
+        use super::{check_token_file, CheckOutcome};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+
+        match check_token_file(&path) {
+            CheckOutcome::Fail(detail) => assert!(detail.contains("no token file")),
+            other => panic!("expected failure, got {:?}", other),
+        }
+
+        std::fs::write(&path, "not json").unwrap();
+        match check_token_file(&path) {
+            CheckOutcome::Fail(detail) => assert!(detail.contains("corrupt")),
+            other => panic!("expected failure, got {:?}", other),
+        }
+
+        std::fs::write(
+            &path,
+            r#"{"access_token":"a","refresh_token":"r","expires_in":3600,"updated_at":0,"username":"bob"}"#,
+        )
+        .unwrap();
+        match check_token_file(&path) {
+            CheckOutcome::Pass(detail) => assert!(detail.contains("bob")),
+            other => panic!("expected pass, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn free_space_check_fires_only_when_space_is_short() {
+        use super::check_free_space;
+
+        assert!(check_free_space(1_000, 1_000).is_ok());
+        assert!(check_free_space(0, 0).is_ok());
+
+        let err = check_free_space(2_000_000, 500_000).unwrap_err();
+        assert!(err.to_string().contains("need 2 MB"));
+        assert!(err.to_string().contains("have 500 kB"));
+    }
+
+    #[test]
+    fn maps_absolute_episode_numbers_across_seasons() {
+        use super::map_absolute_episode;
+        use crate::api::SeriesSeason;
+
+        let seasons: Vec<SeriesSeason> = serde_json::from_str(
+            r#"[
+                {"title": "", "number": 1, "episodes": [
+                    {"title": "", "number": 1, "files": []},
+                    {"title": "", "number": 2, "files": []},
+                    {"title": "", "number": 3, "files": []}
+                ]},
+                {"title": "", "number": 2, "episodes": [
+                    {"title": "", "number": 1, "files": []},
+                    {"title": "", "number": 2, "files": []}
+                ]}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(map_absolute_episode(&seasons, 1).unwrap(), (1, 1));
+        assert_eq!(map_absolute_episode(&seasons, 3).unwrap(), (1, 3));
+        assert_eq!(map_absolute_episode(&seasons, 4).unwrap(), (2, 1));
+        assert_eq!(map_absolute_episode(&seasons, 5).unwrap(), (2, 2));
+
+        assert!(map_absolute_episode(&seasons, 0).is_err());
+        let err = map_absolute_episode(&seasons, 6).unwrap_err();
+        assert!(err.to_string().contains("5 episodes"));
+    }
+
+    #[tokio::test]
+    async fn a_rejected_access_token_is_refreshed_and_the_request_retried_once() {
+        use chrono::Utc;
+
+        use super::App;
+        use crate::api::Config;
+        use crate::auth::storage::{MemoryTokenStorage, TokenStorage};
+        use crate::auth::token::TokenData;
+        use crate::test_util::StubServer;
+
+        let server = StubServer::start(vec![
+            (
+                401,
+                r#"{"status": 401, "message": "token expired"}"#.to_string(),
+            ),
+            (
+                200,
+                r#"{"access_token": "fresh-access", "refresh_token": "fresh-refresh", "expires_in": 3600}"#
+                    .to_string(),
+            ),
+            (
+                200,
+                r#"{"user": {"username": "bob", "reg_date": 0, "subscription": {}}}"#.to_string(),
+            ),
+        ])
+        .await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            ..Config::default()
+        };
+
+        // Locally the token still looks fresh; only the server knows better.
+        let storage = MemoryTokenStorage::new();
+        storage
+            .set(&TokenData {
+                access_token: "stale-access".to_string(),
+                refresh_token: "good-refresh".to_string(),
+                expires_in: 3600,
+                updated_at: Utc::now(),
+                username: None,
+            })
+            .unwrap();
+
+        let app = App::new(&config, &storage);
+        let user = app.current_user().await.unwrap();
+
+        assert_eq!(user.username, "bob");
+        // Rejected request, refresh-token exchange, successful retry.
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[test]
+    fn the_interactive_prompt_accepts_a_pick_and_optional_filters() {
+        let mut input = std::io::Cursor::new("2\n720p\n1\n3-5\n");
+        let mut output = vec![];
+
+        let choice = super::prompt_choice(&mut input, &mut output, 3).unwrap();
+
+        assert_eq!(choice.index, 2);
+        assert_eq!(choice.quality.as_deref(), Some("720p"));
+        assert_eq!(choice.season, Some("1".parse().unwrap()));
+        assert_eq!(choice.episode, Some("3-5".parse().unwrap()));
+    }
+
+    #[test]
+    fn the_interactive_prompt_reasks_after_junk_and_defaults_on_enter() {
+        // "zero" and "99" are out of range, so the pick is asked three times;
+        // the three empty lines keep every optional filter unset.
+        let mut input = std::io::Cursor::new("zero\n99\n1\n\n\n\n");
+        let mut output = vec![];
+
+        let choice = super::prompt_choice(&mut input, &mut output, 3).unwrap();
+
+        assert_eq!(choice.index, 1);
+        assert_eq!(choice.quality, None);
+        assert_eq!(choice.season, None);
+        assert_eq!(choice.episode, None);
+
+        let transcript = String::from_utf8(output).unwrap();
+        assert_eq!(transcript.matches("Pick a result [1-3]: ").count(), 3);
+    }
+
+    #[test]
+    fn the_interactive_prompt_errors_when_input_ends_early() {
+        let mut input = std::io::Cursor::new("");
+        let mut output = vec![];
+
+        let err = super::prompt_choice(&mut input, &mut output, 3).unwrap_err();
+        assert!(err.to_string().contains("input closed"));
     }
 }
@@ -3,7 +3,7 @@ use std::time::Duration;
 
 use anyhow::Result;
 use chrono::Utc;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio::time::{sleep, timeout};
 use url::Url;
@@ -21,6 +21,30 @@ struct CodeResponse {
     interval: u64,
 }
 
+/// A device-code flow that was started but not yet approved. Persisted to a
+/// temp file so an interrupted run resumes polling the same code instead of
+/// making the user start over.
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingDeviceAuth {
+    code: String,
+    user_code: String,
+    verification_uri: String,
+    interval: u64,
+    expires_at: chrono::DateTime<Utc>,
+}
+
+impl From<CodeResponse> for PendingDeviceAuth {
+    fn from(r: CodeResponse) -> Self {
+        PendingDeviceAuth {
+            code: r.code,
+            user_code: r.user_code,
+            verification_uri: r.verification_uri,
+            interval: r.interval,
+            expires_at: Utc::now() + chrono::Duration::seconds(r.expires_in as i64),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct AuthorizationResponseError {
     // status: u16,
@@ -42,6 +66,7 @@ impl From<TokenResponse> for TokenData {
             access_token: r.access_token,
             expires_in: r.expires_in,
             updated_at: Utc::now(),
+            username: None,
         }
     }
 }
@@ -50,6 +75,27 @@ impl From<TokenResponse> for TokenData {
 pub enum AuthError {
     #[error("Authorization error: {0}")]
     Authorization(String),
+    #[error("the user declined the authorization request")]
+    AccessDenied,
+    #[error("the device code expired before the user approved it")]
+    ExpiredToken,
+    #[error("could not reach the authorization server: {0}")]
+    Unreachable(String),
+    #[error("the authorization server rejected the client credentials: {0}")]
+    CredentialsRejected(String),
+}
+
+/// Maps one device-token poll error to the next poll interval, or to the
+/// error that ends the flow. Per the OAuth device-flow spec `slow_down`
+/// means "keep polling, but five seconds less often".
+fn poll_decision(error: &str, interval: u64) -> Result<u64, AuthError> {
+    match error {
+        "authorization_pending" => Ok(interval),
+        "slow_down" => Ok(interval + 5),
+        "access_denied" => Err(AuthError::AccessDenied),
+        "expired_token" => Err(AuthError::ExpiredToken),
+        other => Err(AuthError::Authorization(other.to_string())),
+    }
 }
 
 #[derive(Debug)]
@@ -60,6 +106,7 @@ where
     config: &'a Config,
     storage: &'a Storage,
     client: reqwest::Client,
+    pending_path: std::path::PathBuf,
 }
 
 impl<'a, Storage> Authenticator<'a, Storage>
@@ -67,16 +114,59 @@ where
     Storage: TokenStorage,
 {
     pub fn new(config: &'a Config, storage: &'a Storage) -> Authenticator<'a, Storage> {
-        let client = reqwest::Client::new();
+        // Each poll gets the per-request timeout from the config; the device
+        // code's own `expires_in` still bounds the overall wait.
+        let client = config.http_client().expect("failed to build HTTP client");
 
         Self {
             config,
             client,
             storage,
+            pending_path: std::env::temp_dir().join("kinopub-device-auth.json"),
         }
     }
 
-    async fn get_device_code(&self) -> Result<CodeResponse> {
+    /// Redirects the pending device-auth file, so tests do not share state
+    /// through the real temp directory.
+    #[allow(dead_code)]
+    fn with_pending_path(mut self, pending_path: std::path::PathBuf) -> Self {
+        self.pending_path = pending_path;
+        self
+    }
+
+    /// A previously persisted device code that has not expired yet; expired
+    /// files are removed on sight.
+    fn load_pending(&self) -> Option<PendingDeviceAuth> {
+        let contents = std::fs::read_to_string(&self.pending_path).ok()?;
+        let pending: PendingDeviceAuth = serde_json::from_str(&contents).ok()?;
+
+        if pending.expires_at <= Utc::now() {
+            self.clear_pending();
+            return None;
+        }
+
+        Some(pending)
+    }
+
+    fn save_pending(&self, pending: &PendingDeviceAuth) {
+        if let Ok(contents) = serde_json::to_string(pending) {
+            if let Err(err) = std::fs::write(&self.pending_path, contents) {
+                log::warn!("could not persist pending device auth: {}", err);
+            }
+        }
+    }
+
+    fn clear_pending(&self) {
+        let _ = std::fs::remove_file(&self.pending_path);
+    }
+
+    async fn get_device_code(&self) -> Result<PendingDeviceAuth> {
+        if let Some(pending) = self.load_pending() {
+            log::info!("resuming pending device authorization");
+            self.show_prompt(&pending);
+            return Ok(pending);
+        }
+
         let auth_url = self.build_url("/oauth2/device")?;
 
         let params = [
@@ -85,21 +175,69 @@ where
             ("client_secret", &self.config.client_secret),
         ];
 
-        let result: CodeResponse = self
-            .client
-            .post(auth_url)
-            .form(&params)
-            .send()
-            .await?
-            .json()
-            .await?;
+        // The very first request of a fresh flow deserves the same bounded
+        // backoff as API calls; a startup hiccup should not fail the command.
+        let mut attempt: u32 = 0;
+        let response = loop {
+            let failure = match self.client.post(auth_url.clone()).form(&params).send().await {
+                Ok(response) if response.status().is_success() => break response,
+                // The server answered and said no: retrying cannot fix bad
+                // client credentials.
+                Ok(response) if response.status().is_client_error() => {
+                    let body = response.text().await.unwrap_or_default();
+                    let detail = serde_json::from_str::<AuthorizationResponseError>(&body)
+                        .map(|e| e.error)
+                        .unwrap_or_else(|_| body.trim().to_string());
+
+                    return Err(AuthError::CredentialsRejected(detail).into());
+                }
+                Ok(response) => format!("HTTP {}", response.status()),
+                Err(err) => err.to_string(),
+            };
+
+            if attempt >= self.config.max_retries as u32 {
+                return Err(AuthError::Unreachable(format!(
+                    "{} (after {} retries)",
+                    failure, self.config.max_retries
+                ))
+                .into());
+            }
+
+            let delay = self.config.base_delay * 2u32.saturating_pow(attempt);
+            attempt += 1;
+            log::debug!("device code request failed ({}); retrying in {:?} (attempt {})", failure, delay, attempt);
+            sleep(delay).await;
+        };
+
+        // Decode by hand so an HTML error page produces a readable message
+        // instead of a bare JSON error.
+        let body = response.text().await?;
+        let response: CodeResponse = serde_json::from_str(&body).map_err(|_| {
+            anyhow::anyhow!(
+                "unexpected response from the authorization server: {:.80}",
+                body.trim()
+            )
+        })?;
+
+        let result = PendingDeviceAuth::from(response);
+        self.save_pending(&result);
+        self.show_prompt(&result);
+
+        Ok(result)
+    }
 
+    fn show_prompt(&self, pending: &PendingDeviceAuth) {
         println!(
             "Please enter '{}' at {}",
-            result.user_code, result.verification_uri
+            pending.user_code, pending.verification_uri
         );
 
-        Ok(result)
+        if self.config.qr {
+            match qr_code(&pending.verification_uri) {
+                Ok(code) => println!("{}", code),
+                Err(err) => log::warn!("could not render QR code: {}", err),
+            }
+        }
     }
 
     pub async fn authenticate(&self) -> Result<String> {
@@ -110,7 +248,8 @@ where
                     let result = self.refresh_token(&refresh_token).await;
 
                     if let Some(token) = result {
-                        let token_data = token.into();
+                        let mut token_data: TokenData = token.into();
+                        token_data.username = self.stored_username();
                         self.storage.set(&token_data)?;
 
                         return Ok(token_data.access_token);
@@ -121,11 +260,19 @@ where
 
         let response = self.get_device_code().await?;
 
-        let token = timeout(
-            Duration::from_secs(response.expires_in),
+        let remaining = (response.expires_at - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO);
+
+        let result = timeout(
+            remaining,
             self.wait_for_device_authorization(&response.code, response.interval),
         )
-        .await??;
+        .await;
+
+        // Whether approved or expired, this code is no longer pending.
+        self.clear_pending();
+        let token = result??;
 
         let token_data = token.into();
         self.storage.set(&token_data)?;
@@ -135,6 +282,31 @@ where
         Ok(token_data.access_token)
     }
 
+    /// Like [`Self::authenticate`], but never trusts the cached access token:
+    /// the server has just rejected it, so go straight to the refresh-token
+    /// exchange and fall back to a fresh device flow when that fails too.
+    pub async fn reauthenticate(&self) -> Result<String> {
+        if let Some(refresh_token) = self.storage.get_refresh_token() {
+            if let Some(token) = self.refresh_token(&refresh_token).await {
+                let mut token_data: TokenData = token.into();
+                token_data.username = self.stored_username();
+                self.storage.set(&token_data)?;
+
+                return Ok(token_data.access_token);
+            }
+        }
+
+        // Both tokens are dead; drop them so `authenticate` does not hand the
+        // rejected access token straight back.
+        self.storage.clear()?;
+        self.authenticate().await
+    }
+
+    /// A refreshed token keeps the username recorded on the previous one.
+    fn stored_username(&self) -> Option<String> {
+        self.storage.get_data().and_then(|data| data.username)
+    }
+
     async fn refresh_token(&self, refresh_token: &str) -> Option<TokenResponse> {
         let url = self.build_url("/oauth2/device").ok()?;
 
@@ -159,7 +331,7 @@ where
     async fn wait_for_device_authorization(
         &self,
         code: &str,
-        interval: u64,
+        mut interval: u64,
     ) -> Result<TokenResponse> {
         loop {
             let url = self.build_url("/oauth2/device")?;
@@ -170,6 +342,9 @@ where
                 ("code", code),
             ];
 
+            // One attempt per tick, deliberately without the API client's
+            // retry layer: this POST consumes server-side state, and the loop
+            // itself already provides the pacing.
             let res = self.client.post(url).form(&params).send().await?;
             if res.status().is_success() {
                 let token: TokenResponse = res.json().await?;
@@ -178,8 +353,10 @@ where
             }
 
             let result: AuthorizationResponseError = res.json().await?;
-            if result.error != "authorization_pending" {
-                return Err(AuthError::Authorization(result.error).into());
+            let next_interval = poll_decision(&result.error, interval)?;
+            if next_interval != interval {
+                log::debug!("server asked to slow down; polling every {}s now", next_interval);
+                interval = next_interval;
             }
 
             sleep(Duration::from_secs(interval)).await;
@@ -216,3 +393,238 @@ where
         Ok(Url::parse(&self.config.api_url)?.join(path)?)
     }
 }
+
+/// Terminal rendering of the verification URL as a QR code, two modules per
+/// character cell so it stays small enough for a phone camera to scan.
+fn qr_code(text: &str) -> Result<String> {
+    let code = qrcode::QrCode::new(text.as_bytes())?;
+
+    Ok(code
+        .render::<qrcode::render::unicode::Dense1x2>()
+        .quiet_zone(true)
+        .build())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{qr_code, Authenticator, PendingDeviceAuth};
+    use crate::api::Config;
+    use crate::auth::storage::JsonTokenStorage;
+    use crate::test_util::StubServer;
+
+    const TOKEN_BODY: &str = r#"{
+        "access_token": "fresh-access",
+        "refresh_token": "fresh-refresh",
+        "expires_in": 3600
+    }"#;
+
+    #[tokio::test]
+    async fn resumed_flow_polls_the_stored_code_without_a_new_device_request() {
+        // First scripted response answers the device_token poll; a fresh flow
+        // would instead hit /oauth2/device first and fail to parse this as a
+        // CodeResponse.
+        let server = StubServer::start(vec![
+            (200, TOKEN_BODY.to_string()),
+            (200, "{}".to_string()),
+        ])
+        .await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            ..Config::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+
+        let pending_path = dir.path().join("pending.json");
+        let pending = PendingDeviceAuth {
+            code: "stored-code".to_string(),
+            user_code: "ABCD".to_string(),
+            verification_uri: "https://kino.pub/device".to_string(),
+            interval: 1,
+            expires_at: Utc::now() + chrono::Duration::seconds(300),
+        };
+        std::fs::write(&pending_path, serde_json::to_string(&pending).unwrap()).unwrap();
+
+        let authenticator =
+            Authenticator::new(&config, &storage).with_pending_path(pending_path.clone());
+
+        let access_token = authenticator.authenticate().await.unwrap();
+
+        assert_eq!(access_token, "fresh-access");
+        // Poll plus device notify; no extra device-code request.
+        assert_eq!(server.hits(), 2);
+        // The pending file is cleaned up once the flow finishes.
+        assert!(!pending_path.exists());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_down_grows_the_poll_interval_until_approval() {
+        let server = StubServer::start(vec![
+            (400, r#"{"error": "authorization_pending"}"#.to_string()),
+            (400, r#"{"error": "slow_down"}"#.to_string()),
+            (200, TOKEN_BODY.to_string()),
+            (200, "{}".to_string()),
+        ])
+        .await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            ..Config::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+
+        let pending_path = dir.path().join("pending.json");
+        let pending = PendingDeviceAuth {
+            code: "stored-code".to_string(),
+            user_code: "ABCD".to_string(),
+            verification_uri: "https://kino.pub/device".to_string(),
+            interval: 1,
+            expires_at: Utc::now() + chrono::Duration::seconds(300),
+        };
+        std::fs::write(&pending_path, serde_json::to_string(&pending).unwrap()).unwrap();
+
+        let authenticator =
+            Authenticator::new(&config, &storage).with_pending_path(pending_path);
+
+        let started = tokio::time::Instant::now();
+        let access_token = authenticator.authenticate().await.unwrap();
+
+        assert_eq!(access_token, "fresh-access");
+        assert_eq!(server.hits(), 4);
+        // 1s after authorization_pending, then slow_down raised the interval
+        // to 6s; a fixed interval would only have slept ~2s.
+        assert!(started.elapsed() >= std::time::Duration::from_secs(7));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn device_code_request_retries_through_server_errors() {
+        let code_body = r#"{
+            "code": "fresh-code",
+            "user_code": "ABCD",
+            "verification_uri": "https://kino.pub/device",
+            "expires_in": 300,
+            "interval": 1
+        }"#;
+        let server = StubServer::start(vec![
+            (500, "gateway exploded".to_string()),
+            (500, "gateway exploded".to_string()),
+            (200, code_body.to_string()),
+            (200, TOKEN_BODY.to_string()),
+            (200, "{}".to_string()),
+        ])
+        .await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            ..Config::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+
+        let authenticator = Authenticator::new(&config, &storage)
+            .with_pending_path(dir.path().join("pending.json"));
+
+        let access_token = authenticator.authenticate().await.unwrap();
+
+        assert_eq!(access_token, "fresh-access");
+        // Two failed device-code attempts, the successful one, the token
+        // poll, and the device notify.
+        assert_eq!(server.hits(), 5);
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_fail_without_retrying() {
+        let server = StubServer::start(vec![
+            (400, r#"{"error": "invalid_client"}"#.to_string()),
+        ])
+        .await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            ..Config::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+
+        let authenticator = Authenticator::new(&config, &storage)
+            .with_pending_path(dir.path().join("pending.json"));
+
+        let err = authenticator.authenticate().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<super::AuthError>(),
+            Some(super::AuthError::CredentialsRejected(detail)) if detail == "invalid_client"
+        ));
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_token_poll_post_is_never_retried() {
+        // A retry-friendly config must not re-submit the device-token
+        // exchange: the scripted 500 is the only response, so a second
+        // attempt would hang waiting for a connection.
+        let server = StubServer::start(vec![(500, "gateway exploded".to_string())]).await;
+
+        let config = Config {
+            api_url: server.url.clone(),
+            max_retries: 5,
+            base_delay: std::time::Duration::from_millis(1),
+            ..Config::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+
+        let pending_path = dir.path().join("pending.json");
+        let pending = PendingDeviceAuth {
+            code: "stored-code".to_string(),
+            user_code: "ABCD".to_string(),
+            verification_uri: "https://kino.pub/device".to_string(),
+            interval: 1,
+            expires_at: Utc::now() + chrono::Duration::seconds(300),
+        };
+        std::fs::write(&pending_path, serde_json::to_string(&pending).unwrap()).unwrap();
+
+        let authenticator =
+            Authenticator::new(&config, &storage).with_pending_path(pending_path);
+
+        assert!(authenticator.authenticate().await.is_err());
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn poll_errors_map_to_intervals_or_distinct_failures() {
+        use super::{poll_decision, AuthError};
+
+        assert_eq!(poll_decision("authorization_pending", 5).unwrap(), 5);
+        assert_eq!(poll_decision("slow_down", 5).unwrap(), 10);
+        assert!(matches!(
+            poll_decision("access_denied", 5),
+            Err(AuthError::AccessDenied)
+        ));
+        assert!(matches!(
+            poll_decision("expired_token", 5),
+            Err(AuthError::ExpiredToken)
+        ));
+        assert!(matches!(
+            poll_decision("server_on_fire", 5),
+            Err(AuthError::Authorization(error)) if error == "server_on_fire"
+        ));
+    }
+
+    #[test]
+    fn qr_generation_succeeds_for_a_verification_url() {
+        let rendered = qr_code("https://kino.pub/device").unwrap();
+
+        assert!(!rendered.is_empty());
+        assert!(rendered.lines().count() > 10);
+    }
+}
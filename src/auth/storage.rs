@@ -1,59 +1,594 @@
 use std::fs::File;
 use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use chrono::offset::Utc;
 use chrono::Duration;
 
 use crate::auth::token::{Token, TokenData};
 
+/// Base name of the default account's token file; named accounts use a
+/// `-<name>` suffix before the extension.
+const STORAGE_BASENAME: &str = "kinopub-auth-storage";
+
+/// Token file path for an account inside the config directory. Account names
+/// become part of the filename, so anything that could escape the directory
+/// is rejected.
+pub fn storage_path(config_dir: &Path, account: Option<&str>) -> Result<PathBuf> {
+    match account {
+        None => Ok(config_dir.join(format!("{}.json", STORAGE_BASENAME))),
+        Some(name) => {
+            if name.is_empty() || name.chars().any(|c| std::path::is_separator(c) || c == '.') {
+                bail!("invalid account name '{}'", name);
+            }
+
+            Ok(config_dir.join(format!("{}-{}.json", STORAGE_BASENAME, name)))
+        }
+    }
+}
+
+/// Accounts with a token file in the config directory, as (name, path)
+/// pairs; the unsuffixed file is reported as "default".
+pub fn list_accounts(config_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut accounts = vec![];
+
+    for entry in std::fs::read_dir(config_dir)? {
+        let path = entry?.path();
+
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let middle = match name
+            .strip_prefix(STORAGE_BASENAME)
+            .and_then(|rest| rest.strip_suffix(".json"))
+        {
+            Some(middle) => middle,
+            None => continue,
+        };
+
+        let account = match middle.strip_prefix('-') {
+            Some(account) => account.to_string(),
+            None if middle.is_empty() => "default".to_string(),
+            None => continue,
+        };
+
+        accounts.push((account, path));
+    }
+
+    accounts.sort();
+
+    Ok(accounts)
+}
+
 pub trait TokenStorage {
     fn get(&self) -> Option<Token>;
     fn set(&self, data: &TokenData) -> Result<()>;
+    fn clear(&self) -> Result<()>;
+
+    /// The raw stored token data regardless of access-token validity.
+    /// Backends that cannot produce it lose the extras it carries (the
+    /// refresh token, the recorded username).
+    fn get_data(&self) -> Option<TokenData> {
+        None
+    }
+
+    /// The stored refresh token, for when the server rejects an access token
+    /// that locally still looks fresh.
+    fn get_refresh_token(&self) -> Option<String> {
+        self.get_data().map(|data| data.refresh_token)
+    }
 }
 
 #[derive(Debug)]
 pub struct JsonTokenStorage {
     filename: PathBuf,
+    refresh_skew: Duration,
 }
 
 impl JsonTokenStorage {
-    pub(crate) fn new(filename: PathBuf) -> Self {
-        Self { filename }
+    pub fn new(filename: PathBuf) -> Self {
+        Self {
+            filename,
+            // Treat the access token as expired a minute early so a download
+            // started near the boundary does not run into a 401 mid-way.
+            refresh_skew: Duration::seconds(60),
+        }
+    }
+
+    /// Overrides how long before actual expiry the access token is already
+    /// considered stale.
+    #[allow(dead_code)]
+    pub fn with_refresh_skew(mut self, refresh_skew: Duration) -> Self {
+        self.refresh_skew = refresh_skew;
+        self
     }
 }
 
-impl TokenStorage for JsonTokenStorage {
-    fn get(&self) -> Option<Token> {
+/// Picks which token to use given the stored data: the access token while it
+/// is comfortably valid, the refresh token while the session can still be
+/// renewed, nothing once even that has lapsed.
+fn select_token(token_data: TokenData, refresh_skew: Duration) -> Option<Token> {
+    let expires_at = token_data.updated_at + Duration::seconds(token_data.expires_in as i64);
+
+    if Utc::now() < expires_at - refresh_skew {
+        return Some(Token::AccessToken(token_data.access_token));
+    }
+
+    if Utc::now() < token_data.updated_at + Duration::days(29) {
+        return Some(Token::RefreshToken(token_data.refresh_token));
+    }
+
+    None
+}
+
+impl JsonTokenStorage {
+    fn load(&self) -> Option<TokenData> {
         let file = File::open(&self.filename).ok()?;
 
         let reader = BufReader::new(file);
-        let token_data: TokenData = serde_json::from_reader(reader).unwrap();
-
-        if Utc::now() < token_data.updated_at + Duration::seconds(token_data.expires_in as i64) {
-            return Some(Token::AccessToken(token_data.access_token));
+        match serde_json::from_reader(reader) {
+            Ok(data) => Some(data),
+            Err(err) => {
+                // A truncated or hand-edited file should fall back to a fresh
+                // device-auth flow, not crash the program on startup.
+                log::warn!(
+                    "ignoring unreadable token file {:?}: {}",
+                    self.filename,
+                    err
+                );
+                None
+            }
         }
+    }
+}
 
-        if Utc::now() < token_data.updated_at + Duration::days(29) {
-            return Some(Token::RefreshToken(token_data.refresh_token));
-        }
+impl TokenStorage for JsonTokenStorage {
+    fn get(&self) -> Option<Token> {
+        select_token(self.load()?, self.refresh_skew)
+    }
 
-        None
+    fn get_data(&self) -> Option<TokenData> {
+        self.load()
     }
 
     fn set(&self, token: &TokenData) -> Result<()> {
         log::debug!("saving token: {:?} to {:?}", token, self.filename);
 
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.filename)
-            .unwrap();
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+
+        // The file holds the access and refresh tokens in plaintext, so keep
+        // it readable by the owner only.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let file = options.open(&self.filename)?;
         let writer = BufWriter::new(file);
         serde_json::to_writer(writer, token)?;
 
         Ok(())
     }
+
+    fn clear(&self) -> Result<()> {
+        log::debug!("removing stored token at {:?}", self.filename);
+
+        match std::fs::remove_file(&self.filename) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Name of the environment variable holding a ready-made access token.
+pub const ACCESS_TOKEN_ENV: &str = "KINOPUB_ACCESS_TOKEN";
+
+/// Storage backed by a fixed access token from the environment, for CI and
+/// other ephemeral setups where an interactive device flow is impossible.
+/// There is no refresh token behind it, so a rejected token is final: the
+/// caller has to export a fresh one.
+#[derive(Debug)]
+pub struct EnvTokenStorage {
+    access_token: String,
+}
+
+impl EnvTokenStorage {
+    /// Wraps a token obtained elsewhere; [`Self::from_env`] is the thin
+    /// layer on top, and tests use this directly so they do not have to
+    /// mutate the process environment.
+    pub fn new(access_token: &str) -> Self {
+        Self {
+            access_token: access_token.to_string(),
+        }
+    }
+
+    /// Reads `KINOPUB_ACCESS_TOKEN`; `None` when it is unset or blank.
+    pub fn from_env() -> Option<Self> {
+        match std::env::var(ACCESS_TOKEN_ENV) {
+            Ok(token) if !token.trim().is_empty() => Some(Self::new(token.trim())),
+            _ => None,
+        }
+    }
+}
+
+impl TokenStorage for EnvTokenStorage {
+    fn get(&self) -> Option<Token> {
+        // Never reported as a refresh token: expiry is the server's call, and
+        // a 401 propagates instead of starting a device flow.
+        Some(Token::AccessToken(self.access_token.clone()))
+    }
+
+    fn set(&self, _data: &TokenData) -> Result<()> {
+        // Nothing to persist; the environment is the only source of truth.
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        bail!(
+            "the access token is provided by {} and cannot be refreshed or discarded; \
+             export a fresh token",
+            ACCESS_TOKEN_ENV
+        )
+    }
+}
+
+/// Storage that keeps the token in memory only: nothing touches the disk,
+/// which suits unit tests and library consumers that manage persistence
+/// themselves. Clones share the same underlying slot.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTokenStorage {
+    token: std::sync::Arc<std::sync::Mutex<Option<TokenData>>>,
+    refresh_skew: Option<Duration>,
+}
+
+impl MemoryTokenStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides how long before actual expiry the access token is already
+    /// considered stale; defaults to the same minute `JsonTokenStorage` uses.
+    pub fn with_refresh_skew(mut self, refresh_skew: Duration) -> Self {
+        self.refresh_skew = Some(refresh_skew);
+        self
+    }
+}
+
+impl TokenStorage for MemoryTokenStorage {
+    fn get(&self) -> Option<Token> {
+        let token_data = self.token.lock().unwrap().clone()?;
+
+        select_token(
+            token_data,
+            self.refresh_skew.unwrap_or_else(|| Duration::seconds(60)),
+        )
+    }
+
+    fn set(&self, token: &TokenData) -> Result<()> {
+        *self.token.lock().unwrap() = Some(token.clone());
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        *self.token.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn get_data(&self) -> Option<TokenData> {
+        self.token.lock().unwrap().clone()
+    }
+}
+
+impl TokenStorage for Box<dyn TokenStorage> {
+    fn get(&self) -> Option<Token> {
+        (**self).get()
+    }
+
+    fn set(&self, data: &TokenData) -> Result<()> {
+        (**self).set(data)
+    }
+
+    fn clear(&self) -> Result<()> {
+        (**self).clear()
+    }
+
+    fn get_data(&self) -> Option<TokenData> {
+        (**self).get_data()
+    }
+
+    fn get_refresh_token(&self) -> Option<String> {
+        (**self).get_refresh_token()
+    }
+}
+
+/// Stores the token JSON in the OS secret store instead of a plaintext file.
+#[cfg(feature = "keyring")]
+pub struct KeyringTokenStorage {
+    entry: keyring::Entry,
+    refresh_skew: Duration,
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringTokenStorage {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            entry: keyring::Entry::new("kinopub", "tokens")?,
+            refresh_skew: Duration::seconds(60),
+        })
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringTokenStorage {
+    fn load(&self) -> Option<TokenData> {
+        let secret = self.entry.get_password().ok()?;
+
+        match serde_json::from_str(&secret) {
+            Ok(data) => Some(data),
+            Err(err) => {
+                log::warn!("ignoring unreadable keyring entry: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl TokenStorage for KeyringTokenStorage {
+    fn get(&self) -> Option<Token> {
+        select_token(self.load()?, self.refresh_skew)
+    }
+
+    fn get_data(&self) -> Option<TokenData> {
+        self.load()
+    }
+
+    fn set(&self, token: &TokenData) -> Result<()> {
+        log::debug!("saving token to the OS keyring");
+
+        self.entry.set_password(&serde_json::to_string(token)?)?;
+
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        log::debug!("removing the token from the OS keyring");
+
+        match self.entry.delete_credential() {
+            Ok(()) => Ok(()),
+            Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{JsonTokenStorage, TokenStorage};
+    use crate::auth::token::TokenData;
+
+    #[test]
+    fn clear_removes_the_stored_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"));
+
+        storage
+            .set(&TokenData {
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+                expires_in: 3600,
+                updated_at: Utc::now(),
+                username: None,
+            })
+            .unwrap();
+        assert!(storage.get().is_some());
+
+        storage.clear().unwrap();
+        assert!(storage.get().is_none());
+
+        // Clearing again (missing file) is still a success.
+        storage.clear().unwrap();
+    }
+
+    #[test]
+    fn storage_paths_derive_from_the_account_name() {
+        use std::path::Path;
+
+        use super::storage_path;
+
+        let dir = Path::new("/cfg");
+
+        assert_eq!(
+            storage_path(dir, None).unwrap(),
+            Path::new("/cfg/kinopub-auth-storage.json")
+        );
+        assert_eq!(
+            storage_path(dir, Some("work")).unwrap(),
+            Path::new("/cfg/kinopub-auth-storage-work.json")
+        );
+
+        assert!(storage_path(dir, Some("")).is_err());
+        assert!(storage_path(dir, Some("../evil")).is_err());
+        assert!(storage_path(dir, Some("a/b")).is_err());
+    }
+
+    #[test]
+    fn accounts_are_discovered_from_token_files() {
+        use super::list_accounts;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("kinopub-auth-storage.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("kinopub-auth-storage-work.json"), "{}").unwrap();
+        std::fs::write(dir.path().join("unrelated.json"), "{}").unwrap();
+
+        let accounts = list_accounts(dir.path()).unwrap();
+        let names: Vec<_> = accounts.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(names, vec!["default", "work"]);
+    }
+
+    #[test]
+    fn tokens_inside_the_skew_window_trigger_a_refresh() {
+        use chrono::Duration;
+
+        use crate::auth::token::Token;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = JsonTokenStorage::new(dir.path().join("token.json"))
+            .with_refresh_skew(Duration::seconds(60));
+
+        let data = |expires_in| TokenData {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_in,
+            updated_at: Utc::now(),
+            username: None,
+        };
+
+        // Plenty of time left: the access token is still usable.
+        storage.set(&data(3600)).unwrap();
+        assert!(matches!(storage.get(), Some(Token::AccessToken(_))));
+
+        // Expires within the skew: refresh proactively.
+        storage.set(&data(30)).unwrap();
+        assert!(matches!(storage.get(), Some(Token::RefreshToken(_))));
+
+        // Already expired: still refreshable for a while.
+        storage.set(&data(0)).unwrap();
+        assert!(matches!(storage.get(), Some(Token::RefreshToken(_))));
+    }
+
+    #[test]
+    fn memory_storage_walks_the_expiry_transitions() {
+        use chrono::Duration;
+
+        use super::MemoryTokenStorage;
+        use crate::auth::token::Token;
+
+        let storage = MemoryTokenStorage::new().with_refresh_skew(Duration::seconds(60));
+
+        assert!(storage.get().is_none());
+
+        let data = |expires_in, age_days| TokenData {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_in,
+            updated_at: Utc::now() - Duration::days(age_days),
+            username: None,
+        };
+
+        // Fresh token: usable directly.
+        storage.set(&data(3600, 0)).unwrap();
+        assert!(matches!(storage.get(), Some(Token::AccessToken(_))));
+
+        // Expired but recent: refreshable.
+        storage.set(&data(0, 0)).unwrap();
+        assert!(matches!(storage.get(), Some(Token::RefreshToken(_))));
+
+        // Older than the refresh window: gone entirely.
+        storage.set(&data(0, 30)).unwrap();
+        assert!(storage.get().is_none());
+
+        storage.set(&data(3600, 0)).unwrap();
+        storage.clear().unwrap();
+        assert!(storage.get().is_none());
+    }
+
+    #[test]
+    fn env_storage_serves_the_token_and_refuses_to_clear() {
+        use super::EnvTokenStorage;
+        use crate::auth::token::Token;
+
+        std::env::set_var(super::ACCESS_TOKEN_ENV, "ci-token");
+        let storage = EnvTokenStorage::from_env().unwrap();
+        std::env::remove_var(super::ACCESS_TOKEN_ENV);
+        assert!(EnvTokenStorage::from_env().is_none());
+
+        match storage.get() {
+            Some(Token::AccessToken(token)) => assert_eq!(token, "ci-token"),
+            other => panic!("expected an access token, got {:?}", other),
+        }
+        // No refresh token: a rejected access token must not loop through
+        // the refresh exchange.
+        assert!(storage.get_refresh_token().is_none());
+        assert!(storage.clear().is_err());
+    }
+
+    #[test]
+    fn corrupt_token_files_are_treated_as_absent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        let storage = JsonTokenStorage::new(path.clone());
+
+        std::fs::write(&path, b"{\"access_token\": \"trunc").unwrap();
+        assert!(storage.get().is_none());
+
+        std::fs::write(&path, b"not json at all").unwrap();
+        assert!(storage.get().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn token_file_is_only_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        let storage = JsonTokenStorage::new(path.clone());
+
+        storage
+            .set(&TokenData {
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+                expires_in: 3600,
+                updated_at: Utc::now(),
+                username: None,
+            })
+            .unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
+
+#[cfg(all(test, feature = "keyring"))]
+mod keyring_tests {
+    use chrono::Utc;
+
+    use super::{KeyringTokenStorage, TokenStorage};
+    use crate::auth::token::TokenData;
+
+    #[test]
+    fn round_trips_through_a_mock_keyring() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+
+        let storage = KeyringTokenStorage::new().unwrap();
+
+        assert!(storage.get().is_none());
+
+        storage
+            .set(&TokenData {
+                access_token: "access".to_string(),
+                refresh_token: "refresh".to_string(),
+                expires_in: 3600,
+                updated_at: Utc::now(),
+                username: None,
+            })
+            .unwrap();
+        assert!(storage.get().is_some());
+
+        storage.clear().unwrap();
+        assert!(storage.get().is_none());
+
+        // Clearing again (missing entry) is still a success.
+        storage.clear().unwrap();
+    }
 }
@@ -2,7 +2,7 @@ use chrono::serde::ts_seconds::{deserialize as from_ts, serialize as to_ts};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenData {
     pub access_token: String,
     pub refresh_token: String,
@@ -10,6 +10,11 @@ pub struct TokenData {
 
     #[serde(serialize_with = "to_ts", deserialize_with = "from_ts")]
     pub updated_at: DateTime<Utc>,
+
+    /// Account name shown without an extra API round-trip; absent in token
+    /// files written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
 }
 
 #[derive(Debug)]
@@ -19,3 +24,34 @@ pub enum Token {
 }
 
 impl TokenData {}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::TokenData;
+
+    #[test]
+    fn username_round_trips_and_defaults_when_absent() {
+        let data = TokenData {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            expires_in: 3600,
+            updated_at: Utc::now(),
+            username: Some("bob".to_string()),
+        };
+
+        let json = serde_json::to_string(&data).unwrap();
+        let back: TokenData = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.username.as_deref(), Some("bob"));
+
+        // Token files from before the field existed still load.
+        let legacy =
+            r#"{"access_token":"a","refresh_token":"r","expires_in":3600,"updated_at":0}"#;
+        let back: TokenData = serde_json::from_str(legacy).unwrap();
+        assert_eq!(back.username, None);
+
+        // And an absent username is not written out as `null`.
+        assert!(!serde_json::to_string(&back).unwrap().contains("username"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Persistent defaults read from the TOML config file. Every field is
+/// optional; a CLI flag always takes precedence over the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub quality: Option<String>,
+    pub threads: Option<u64>,
+    pub output_dir: Option<PathBuf>,
+    pub proxy: Option<String>,
+    /// Extra quality synonyms, e.g. `best = "2160p"` under
+    /// `[quality_aliases]`; they win over the built-in table.
+    #[serde(default)]
+    pub quality_aliases: std::collections::HashMap<String, String>,
+}
+
+/// Loads the config file. An explicitly given path must exist and parse; the
+/// default `~/.config/kinopub-downloader.toml` is simply skipped when absent.
+pub fn load_config(path: Option<PathBuf>) -> Result<FileConfig> {
+    let (path, required) = match path {
+        Some(path) => (path, true),
+        None => match dirs::config_dir() {
+            Some(dir) => (dir.join("kinopub-downloader.toml"), false),
+            None => return Ok(FileConfig::default()),
+        },
+    };
+
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) if !required => return Ok(FileConfig::default()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("cannot read config file {:?}", path))
+        }
+    };
+
+    toml::from_str(&contents).with_context(|| format!("invalid config file {:?}", path))
+}
+
+/// Merge rule shared by all settings: the flag value wins, the file only
+/// fills gaps.
+pub fn merge<T>(flag: Option<T>, file: Option<T>) -> Option<T> {
+    flag.or(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_config, merge};
+
+    #[test]
+    fn flag_values_win_over_file_values() {
+        assert_eq!(merge(Some("flag"), Some("file")), Some("flag"));
+        assert_eq!(merge(None, Some("file")), Some("file"));
+        assert_eq!(merge(Some("flag"), None), Some("flag"));
+        assert_eq!(merge::<&str>(None, None), None);
+    }
+
+    #[test]
+    fn parses_a_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "quality = \"1080p\"\nthreads = 8\noutput_dir = \"/media\"\n",
+        )
+        .unwrap();
+
+        let config = load_config(Some(path)).unwrap();
+
+        assert_eq!(config.quality.as_deref(), Some("1080p"));
+        assert_eq!(config.threads, Some(8));
+        assert_eq!(config.output_dir, Some("/media".into()));
+        assert_eq!(config.proxy, None);
+    }
+
+    #[test]
+    fn an_explicit_path_must_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_config(Some(dir.path().join("missing.toml"))).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "qualty = \"1080p\"\n").unwrap();
+
+        assert!(load_config(Some(path)).is_err());
+    }
+}
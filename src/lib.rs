@@ -0,0 +1,46 @@
+//! Library surface of the kino.pub downloader, so the same logic can back
+//! other frontends (a GUI, a bot) without shelling out to the CLI binary.
+//!
+//! ```
+//! use kinopub::auth::storage::TokenStorage;
+//! use kinopub::auth::token::{Token, TokenData};
+//! use kinopub::{App, Config};
+//!
+//! /// Storage that never persists anything; a real embedder would keep the
+//! /// token wherever its host application stores secrets.
+//! struct NullStorage;
+//!
+//! impl TokenStorage for NullStorage {
+//!     fn get(&self) -> Option<Token> {
+//!         None
+//!     }
+//!
+//!     fn set(&self, _data: &TokenData) -> anyhow::Result<()> {
+//!         Ok(())
+//!     }
+//!
+//!     fn clear(&self) -> anyhow::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let config = Config::default();
+//! let storage = NullStorage;
+//! let app = App::new(&config, &storage);
+//! ```
+
+pub mod api;
+pub mod app;
+pub mod auth;
+pub mod file_config;
+pub mod logging;
+pub mod parallel_downloader;
+pub mod selector;
+#[cfg(test)]
+mod test_util;
+pub mod utils;
+
+pub use api::{ApiClient, Config};
+pub use app::App;
+pub use auth::storage::TokenStorage;
+pub use parallel_downloader::{DownloadEvent, Downloader};
@@ -1,42 +1,84 @@
-#![feature(result_option_inspect)]
-
 use anyhow::Result;
 use clap::Parser;
 use cli_table::{print_stdout, WithTitle};
 
-use crate::app::App;
+use kinopub::app::App;
+use kinopub::auth::storage::TokenStorage;
+use kinopub::{api, app, auth, file_config};
 
-mod api;
-mod app;
-mod auth;
+/// Selects the token backend from `--token-store`. The keyring variant only
+/// exists in builds with the `keyring` cargo feature enabled.
+fn build_storage(cli: &app::Cli, token_path: std::path::PathBuf) -> Result<Box<dyn TokenStorage>> {
+    // CI and other ephemeral environments inject a token directly and never
+    // see a device-auth prompt; the variable beats any --token-store choice.
+    if let Some(storage) = auth::storage::EnvTokenStorage::from_env() {
+        return Ok(Box::new(storage));
+    }
 
-mod parallel_downloader;
-mod utils;
+    match cli.token_store {
+        app::TokenStore::File => Ok(Box::new(auth::storage::JsonTokenStorage::new(token_path))),
+        #[cfg(feature = "keyring")]
+        app::TokenStore::Keyring => Ok(Box::new(auth::storage::KeyringTokenStorage::new()?)),
+        #[cfg(not(feature = "keyring"))]
+        app::TokenStore::Keyring => anyhow::bail!(
+            "this build has no keyring support; rebuild with `--features keyring`"
+        ),
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = app::Cli::parse();
 
-    let mut logger = simple_logger::SimpleLogger::new().with_utc_timestamps();
-
-    logger = match cli.verbose {
-        1 => logger.with_level(log::LevelFilter::Info),
-        2 => logger.with_level(log::LevelFilter::Debug),
-        3 => logger.with_level(log::LevelFilter::Trace),
-        _ => logger.with_level(log::LevelFilter::Error),
+    let level = match cli.verbose {
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        3 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Error,
     };
 
-    logger.init()?;
+    // A log file keeps verbose output from interleaving with the progress
+    // bars; without one the logs go to the terminal as before.
+    match &cli.log_file {
+        Some(path) => kinopub::logging::FileLogger::new(path, level)?.init()?,
+        None => simple_logger::SimpleLogger::new()
+            .with_utc_timestamps()
+            .with_level(level)
+            .init()?,
+    }
 
-    let token_path = dirs::config_dir()
-        .unwrap()
-        .join("kinopub-auth-storage.json");
+    let config_dir = dirs::config_dir().unwrap();
+    let token_path = auth::storage::storage_path(&config_dir, cli.account.as_deref())?;
 
     log::debug!("auth storage path: {:?}", token_path);
 
-    let storage = auth::storage::JsonTokenStorage::new(token_path);
+    let storage = build_storage(&cli, token_path.clone())?;
+    let file_defaults = file_config::load_config(cli.config.clone())?;
+
     let mut config = api::Config::default();
-    config.set_threads_count(cli.threads);
+    config.set_threads_count(
+        file_config::merge(cli.threads, file_defaults.threads).unwrap_or(4),
+    );
+    config.set_proxy(cli.proxy.clone());
+    if config.proxy.is_none() {
+        config.proxy = file_defaults.proxy.clone();
+    }
+    config.set_no_proxy(cli.no_proxy);
+    config.set_ip_family(match (cli.prefer_ipv4, cli.prefer_ipv6) {
+        (true, _) => Some(api::IpFamily::V4),
+        (_, true) => Some(api::IpFamily::V6),
+        _ => None,
+    });
+    config.set_pool_tuning(cli.pool_max_idle_per_host, cli.pool_idle_timeout);
+    config.set_qr(cli.qr);
+    config.set_api_overrides(
+        cli.api_url.clone(),
+        cli.client_id.clone(),
+        cli.client_secret.clone(),
+    );
+
+    // Surface a malformed proxy URL immediately instead of on first request.
+    config.http_client()?;
 
     let app_instance = App::new(&config, &storage);
 
@@ -44,6 +86,8 @@ async fn main() -> Result<()> {
         app::Commands::Authenticate => {
             let current_user = app_instance.current_user().await?;
 
+            app_instance.remember_username(&current_user.username)?;
+
             println!(
                 "Hello, {}!\nYou are successfully authenticated!",
                 current_user.username
@@ -54,19 +98,252 @@ async fn main() -> Result<()> {
             quality,
             season,
             episode,
+            exclude_season,
+            exclude_episode,
+            absolute,
+            by_index,
+            part,
+            name_template,
+            no_space_check,
+            output,
+            output_dir,
+            flat,
+            list_qualities,
+            fallback_quality,
+            max_rate,
+            limit_rate_per_connection,
+            chunk_size,
+            head,
+            range,
+            dry_run,
+            print_url,
+            print_url_with_name,
+            skip_existing,
+            overwrite,
+            no_clobber: _,
+            write_nfo,
+            write_poster,
+            new_episodes,
+            save_json,
+            retries,
+            timeout_secs,
+            sha256,
+            md5,
+            parallel_items,
+            subtitles,
+            audio,
+            lang,
+            codec,
         } => {
+            use std::io::IsTerminal;
+
+            use kinopub::selector::EpisodeSelector;
+
             app_instance
                 .download(
-                    id.to_owned(),
-                    quality.to_owned(),
-                    season.to_owned(),
-                    episode.to_owned(),
+                    id.id,
+                    app::DownloadOptions {
+                        quality: file_config::merge(
+                            quality.to_owned(),
+                            file_defaults.quality.clone(),
+                        ),
+                        quality_aliases: file_defaults.quality_aliases.clone(),
+                        season: season
+                            .to_owned()
+                            .or_else(|| id.season.map(EpisodeSelector::single)),
+                        episode: episode
+                            .to_owned()
+                            .or_else(|| id.episode.map(EpisodeSelector::single)),
+                        exclude_season: exclude_season.to_owned(),
+                        exclude_episode: exclude_episode.to_owned(),
+                        absolute: *absolute,
+                        by_index: *by_index,
+                        part: *part,
+                        name_template: name_template.to_owned(),
+                        no_space_check: *no_space_check,
+                        output: output.to_owned(),
+                        output_dir: file_config::merge(
+                            output_dir.to_owned(),
+                            file_defaults.output_dir.clone(),
+                        ),
+                        flat: *flat,
+                        list_qualities: *list_qualities,
+                        fallback_quality: *fallback_quality,
+                        max_rate: *max_rate,
+                        limit_rate_per_connection: *limit_rate_per_connection,
+                        chunk_size: *chunk_size,
+                        head: *head,
+                        range: *range,
+                        dry_run: *dry_run,
+                        print_url: *print_url,
+                        print_url_with_name: *print_url_with_name,
+                        skip_existing: *skip_existing,
+                        overwrite: *overwrite,
+                        write_nfo: *write_nfo,
+                        write_poster: *write_poster,
+                        new_episodes: *new_episodes,
+                        save_json: save_json.to_owned(),
+                        retries: *retries,
+                        timeout_secs: *timeout_secs,
+                        sha256: sha256.to_owned(),
+                        md5: md5.to_owned(),
+                        parallel_items: *parallel_items,
+                        subtitles: subtitles
+                            .as_ref()
+                            .map(|langs| langs.first().cloned()),
+                        audio: audio.to_owned(),
+                        lang: lang.to_owned(),
+                        codec: codec.to_owned(),
+                        // Explicit flag, or stdout is not a terminal (logs,
+                        // pipes, CI) where bar redraws are just noise.
+                        quiet: cli.quiet || !std::io::stdout().is_terminal(),
+                    },
                 )
                 .await?
         }
-        app::Commands::Search { query } => {
-            print_stdout(app_instance.search(query).await?.with_title())?;
+        app::Commands::Info { id, save_json } => {
+            app_instance.info(*id, save_json.as_deref()).await?
+        }
+        app::Commands::Accounts {
+            command: app::AccountsCommand::List,
+        } => {
+            for (account, path) in auth::storage::list_accounts(&config_dir)? {
+                let storage = auth::storage::JsonTokenStorage::new(path);
+                let account_app = App::new(&config, &storage);
+
+                // The recorded username avoids a network round-trip; tokens
+                // from before it was recorded are queried as before, and a
+                // stale token shows as "-" rather than kicking off a
+                // device-auth flow.
+                let username = match storage.get_data().and_then(|data| data.username) {
+                    Some(username) => username,
+                    None => match storage.get() {
+                        Some(_) => account_app
+                            .current_user()
+                            .await
+                            .map(|user| user.username)
+                            .unwrap_or_else(|_| "-".to_string()),
+                        None => "-".to_string(),
+                    },
+                };
+
+                println!("{}\t{}", account, username);
+            }
+        }
+        app::Commands::Logout => {
+            storage.clear()?;
+            println!("Logged out. Stored credentials have been removed.");
+        }
+        app::Commands::Search {
+            query,
+            kind,
+            json,
+            limit,
+            page,
+        } => {
+            let mut results = app_instance.search(query, *limit, *page).await?;
+
+            if let Some(kinds) = kind {
+                results.items = api::search::filter_by_type(results.items, kinds);
+            }
+
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&results.items)?);
+            } else {
+                print_stdout(results.items.with_title())?;
+
+                if let Some(total) = results.pagination.as_ref().and_then(|p| p.total) {
+                    println!("Showing {} of {} results", results.items.len(), total);
+                }
+            }
+        }
+        app::Commands::Interactive { query } => {
+            use std::io::IsTerminal;
+
+            app_instance
+                .interactive(query, cli.quiet || !std::io::stdout().is_terminal())
+                .await?
+        }
+        app::Commands::Browse {
+            genre,
+            kind,
+            sort,
+            year_from,
+            year_to,
+            limit,
+            page,
+        } => {
+            let results = app_instance
+                .browse(kinopub::api::ItemsFilter {
+                    genre: *genre,
+                    kind: kind.to_owned(),
+                    sort: sort.to_owned(),
+                    year_from: *year_from,
+                    year_to: *year_to,
+                    limit: *limit,
+                    page: *page,
+                })
+                .await?;
+
+            print_stdout(results.items.with_title())?;
+
+            if let Some(total) = results.pagination.as_ref().and_then(|p| p.total) {
+                println!("Showing {} of {} results", results.items.len(), total);
+            }
+        }
+        app::Commands::Update { check } => {
+            let outdated = app_instance.check_update().await?;
+            if *check && outdated {
+                std::process::exit(1);
+            }
+        }
+        app::Commands::Doctor => {
+            app_instance.doctor(&config_dir, &token_path).await?;
+        }
+        app::Commands::Watching => {
+            let result = app_instance.watching().await?;
+
+            print_stdout(result.items.with_title())?;
         }
+        app::Commands::Bookmarks { folder, command } => match (folder, command) {
+            (None, None) => {
+                let result = app_instance.bookmark_folders().await?;
+
+                print_stdout(result.items.with_title())?;
+            }
+            (Some(folder), None) => {
+                let folder = app_instance.resolve_bookmark_folder(folder).await?;
+                let result = app_instance.bookmark_items(folder.id).await?;
+
+                print_stdout(result.items.with_title())?;
+            }
+            (Some(folder), Some(app::BookmarksCommand::Download)) => {
+                use std::io::IsTerminal;
+
+                let folder = app_instance.resolve_bookmark_folder(folder).await?;
+                let items = app_instance.bookmark_items(folder.id).await?.items;
+
+                for item in items {
+                    app_instance
+                        .download(
+                            item.id,
+                            app::DownloadOptions {
+                                quality: file_defaults.quality.clone(),
+                                quality_aliases: file_defaults.quality_aliases.clone(),
+                                output_dir: file_defaults.output_dir.clone(),
+                                retries: 3,
+                                parallel_items: 1,
+                                quiet: cli.quiet || !std::io::stdout().is_terminal(),
+                                ..Default::default()
+                            },
+                        )
+                        .await?;
+                }
+            }
+            (None, Some(app::BookmarksCommand::Download)) => {
+                anyhow::bail!("bookmarks download requires --folder")
+            }
+        },
     }
 
     Ok(())
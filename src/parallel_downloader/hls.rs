@@ -0,0 +1,192 @@
+//! Minimal HLS support: fetches a media playlist and stitches its segments
+//! into one file. Master playlists, encryption, and `EXT-X-BYTERANGE` are out
+//! of scope; the playlists kinopub serves do not use them.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use futures::future::try_join_all;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use url::Url;
+
+use super::{append_extension, summary_line, Downloader};
+
+/// URLs that name a playlist outright, before any request is made.
+pub(super) fn is_hls_url(url: &str) -> bool {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.to_ascii_lowercase().ends_with(".m3u8")
+}
+
+/// Content types servers use for playlists behind extension-less URLs.
+pub(super) fn is_hls_content_type(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or(content_type).trim();
+
+    essence.eq_ignore_ascii_case("application/vnd.apple.mpegurl")
+        || essence.eq_ignore_ascii_case("application/x-mpegurl")
+}
+
+/// Segment URLs in playlist order, resolved against the playlist's own URL so
+/// relative entries work.
+fn segment_urls(playlist: &str, base: &Url) -> Result<Vec<String>> {
+    playlist
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Ok(base.join(line)?.to_string()))
+        .collect()
+}
+
+impl Downloader {
+    /// Fetches the playlist, downloads every segment concurrently (bounded by
+    /// `threads`), and concatenates them in playlist order. Segments are
+    /// buffered in memory; they are a few megabytes each.
+    pub(super) async fn download_hls(
+        &self,
+        url: &str,
+        title: &str,
+        save_to: &Path,
+        threads: u64,
+    ) -> Result<u64> {
+        let started = std::time::Instant::now();
+
+        let base = Url::parse(url)?;
+        let playlist = crate::utils::send_traced(&self.client, self.client.get(url))
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        let segments = segment_urls(&playlist, &base)?;
+        if segments.is_empty() {
+            bail!("playlist '{}' lists no segments", url);
+        }
+
+        // Segment counts stand in for bytes here; the playlist does not say
+        // how large the stream is until every segment has arrived.
+        let progress = match &self.progress_bar {
+            Some(progress) => progress.clone(),
+            None if self.quiet => ProgressBar::hidden(),
+            None => {
+                let progress = ProgressBar::new(0);
+
+                match &self.multi_progress {
+                    Some(multi) => {
+                        multi.add(progress.clone());
+                    }
+                    None => progress.set_draw_target(ProgressDrawTarget::stdout_with_hz(10)),
+                }
+
+                progress
+            }
+        };
+        progress.set_style(ProgressStyle::default_bar().template("{msg} {pos}/{len} segments"));
+        progress.set_length(segments.len() as u64);
+        progress.set_message(title.to_owned());
+
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(threads.max(1) as usize));
+        let downloads = segments.into_iter().map(|segment| {
+            let client = self.client.clone();
+            let semaphore = semaphore.clone();
+            let progress = progress.clone();
+
+            async move {
+                let _permit = semaphore.acquire_owned().await?;
+
+                let body = crate::utils::send_traced(&client, client.get(&segment))
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await?;
+                progress.inc(1);
+
+                Ok::<_, anyhow::Error>(body)
+            }
+        });
+
+        let bodies = try_join_all(downloads).await?;
+        progress.finish_and_clear();
+
+        let part_path = append_extension(save_to, ".part");
+        let mut file = std::fs::File::create(&part_path)?;
+        let mut total_size = 0u64;
+
+        for body in &bodies {
+            file.write_all(body)?;
+            total_size += body.len() as u64;
+        }
+
+        drop(file);
+        std::fs::rename(&part_path, save_to)?;
+
+        println!("{}", summary_line(title, total_size, started.elapsed()));
+
+        Ok(total_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use super::{is_hls_content_type, is_hls_url, segment_urls};
+    use crate::parallel_downloader::Downloader;
+    use crate::test_util::StubServer;
+
+    #[test]
+    fn playlists_are_detected_by_url_or_content_type() {
+        assert!(is_hls_url("http://host/video.m3u8"));
+        assert!(is_hls_url("http://host/video.M3U8?token=x"));
+        assert!(!is_hls_url("http://host/video.mp4"));
+        assert!(!is_hls_url("http://host/video?name=a.m3u8"));
+
+        assert!(is_hls_content_type("application/vnd.apple.mpegurl"));
+        assert!(is_hls_content_type("application/x-mpegURL; charset=utf-8"));
+        assert!(!is_hls_content_type("video/mp4"));
+    }
+
+    #[test]
+    fn segments_resolve_against_the_playlist_url() {
+        let base = Url::parse("http://host/streams/video.m3u8").unwrap();
+        let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:10\n\n#EXTINF:10,\nseg0.ts\n#EXTINF:10,\n/abs/seg1.ts\n#EXT-X-ENDLIST\n";
+
+        assert_eq!(
+            segment_urls(playlist, &base).unwrap(),
+            vec![
+                "http://host/streams/seg0.ts".to_string(),
+                "http://host/abs/seg1.ts".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_playlist_download_concatenates_the_segments() {
+        let playlist = "#EXTM3U\n#EXTINF:10,\nseg0.ts\n#EXTINF:10,\nseg1.ts\n#EXTINF:10,\nseg2.ts\n#EXT-X-ENDLIST\n";
+        // One thread keeps the segment requests in playlist order, which is
+        // the order the scripted responses are served in.
+        let server = StubServer::start(vec![
+            (200, playlist.to_string()),
+            (200, "first ".to_string()),
+            (200, "second ".to_string()),
+            (200, "third".to_string()),
+        ])
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("video.ts");
+
+        let url = format!("{}/video.m3u8", server.url);
+        let bytes = Downloader::default()
+            .download_to(&url, "video.ts", save_to.clone(), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, "first second third".len() as u64);
+        assert_eq!(
+            std::fs::read_to_string(&save_to).unwrap(),
+            "first second third"
+        );
+        assert_eq!(server.hits(), 4);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Current manifest format. Bumped on any incompatible change, so an old
+/// binary never misreads a newer file as resume state. Version 2 added the
+/// byte-window start.
+const MANIFEST_VERSION: u32 = 2;
+
+/// Sidecar state for a partially downloaded file. Records which inclusive
+/// byte ranges have been fully written so an interrupted download can skip
+/// them on the next run, even when the chunking changes (e.g. a different
+/// `--threads` value).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResumeManifest {
+    /// Format version; `load` discards anything it does not know. Files from
+    /// before the field existed default to 0 and are discarded too.
+    #[serde(default)]
+    version: u32,
+    /// Source URL of the part file, so state never seeds a resume of a
+    /// different download that happens to share the path.
+    #[serde(default)]
+    pub url: String,
+    /// Start of the byte window the completed offsets are relative to. Two
+    /// `--range` windows of equal length look alike otherwise, and resuming
+    /// across them would splice bytes from the wrong window.
+    pub range_start: u64,
+    pub total_size: u64,
+    completed: Vec<(u64, u64)>,
+}
+
+impl ResumeManifest {
+    pub fn new(url: &str, range_start: u64, total_size: u64) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            url: url.to_owned(),
+            range_start,
+            total_size,
+            completed: vec![],
+        }
+    }
+
+    /// Loads resume state, but only when it is usable: a manifest with an
+    /// unknown version, for another URL, or for another byte window yields
+    /// `None` (with a warning), so the download restarts from scratch
+    /// instead of corrupting the file.
+    pub fn load(path: &Path, url: &str, range_start: u64) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        let manifest: Self = serde_json::from_reader(BufReader::new(file)).ok()?;
+
+        if manifest.version != MANIFEST_VERSION {
+            log::warn!(
+                "resume manifest '{}' has unsupported version {}; starting over",
+                path.display(),
+                manifest.version
+            );
+            return None;
+        }
+
+        if manifest.url != url {
+            log::warn!(
+                "resume manifest '{}' belongs to a different URL; starting over",
+                path.display()
+            );
+            return None;
+        }
+
+        if manifest.range_start != range_start {
+            log::warn!(
+                "resume manifest '{}' was written for a different byte range; starting over",
+                path.display()
+            );
+            return None;
+        }
+
+        Some(manifest)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)?;
+        serde_json::to_writer(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    /// Marks an inclusive byte range as fully flushed, merging adjacent and
+    /// overlapping entries so the list stays small.
+    pub fn mark_complete(&mut self, start: u64, end: u64) {
+        self.completed.push((start, end));
+        self.completed.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = vec![];
+        for (start, end) in self.completed.drain(..) {
+            match merged.last_mut() {
+                Some((_, merged_end)) if start <= *merged_end + 1 => {
+                    *merged_end = (*merged_end).max(end)
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+
+        self.completed = merged;
+    }
+
+    /// True when every byte of the inclusive range is already covered.
+    pub fn is_complete(&self, start: u64, end: u64) -> bool {
+        self.completed
+            .iter()
+            .any(|(s, e)| *s <= start && end <= *e)
+    }
+
+    pub fn completed_bytes(&self) -> u64 {
+        self.completed.iter().map(|(s, e)| e - s + 1).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResumeManifest;
+
+    const URL: &str = "http://example.com/file.bin";
+
+    #[test]
+    fn merges_adjacent_ranges() {
+        let mut manifest = ResumeManifest::new(URL, 0, 100);
+        manifest.mark_complete(0, 24);
+        manifest.mark_complete(50, 74);
+        manifest.mark_complete(25, 49);
+
+        assert!(manifest.is_complete(0, 74));
+        assert_eq!(manifest.completed_bytes(), 75);
+    }
+
+    #[test]
+    fn resumes_with_different_chunking() {
+        let mut manifest = ResumeManifest::new(URL, 0, 100);
+        // First run used 25-byte chunks and finished the first two.
+        manifest.mark_complete(0, 24);
+        manifest.mark_complete(25, 49);
+
+        // Second run uses 50-byte chunks: the first is covered, the rest not.
+        assert!(manifest.is_complete(0, 49));
+        assert!(!manifest.is_complete(50, 99));
+    }
+
+    #[test]
+    fn round_trips_through_disk() {
+        let path = std::env::temp_dir().join(format!("manifest-{}.json", std::process::id()));
+
+        let mut manifest = ResumeManifest::new(URL, 0, 42);
+        manifest.mark_complete(0, 9);
+        manifest.save(&path).unwrap();
+
+        assert_eq!(ResumeManifest::load(&path, URL, 0), Some(manifest));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_manifest_for_another_url_version_or_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+
+        let mut manifest = ResumeManifest::new(URL, 0, 42);
+        manifest.mark_complete(0, 9);
+        manifest.save(&path).unwrap();
+
+        // Same file, different source: no resume.
+        assert_eq!(
+            ResumeManifest::load(&path, "http://example.com/other.bin", 0),
+            None
+        );
+
+        // Same URL and window length, different window start: the offsets
+        // inside belong to other bytes, so no resume.
+        assert_eq!(ResumeManifest::load(&path, URL, 100), None);
+
+        // A future (or pre-versioning) format: no resume either.
+        let mut doctored: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        doctored["version"] = serde_json::json!(99);
+        std::fs::write(&path, doctored.to_string()).unwrap();
+        assert_eq!(ResumeManifest::load(&path, URL, 0), None);
+
+        // Manifests written before the version field carry none at all.
+        let legacy = r#"{"total_size": 42, "completed": [[0, 9]]}"#;
+        std::fs::write(&path, legacy).unwrap();
+        assert_eq!(ResumeManifest::load(&path, URL, 0), None);
+    }
+}
@@ -1,51 +1,267 @@
 use anyhow::{anyhow, Result};
 use futures::future::try_join_all;
 use futures_util::StreamExt;
-use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, RANGE};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_TYPE, RANGE};
 use reqwest::Client;
-use std::fs::File;
-use std::io::{Seek, Write};
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::task::JoinHandle;
 
+use crate::parallel_downloader::manifest::ResumeManifest;
+use crate::parallel_downloader::rate_limiter::RateLimiter;
+
+mod hls;
+pub mod manifest;
+pub mod rate_limiter;
+
+/// Progress notifications for embedders that cannot watch the terminal bar.
+/// Per download the order is one `Started`, any number of `Progress` with the
+/// cumulative byte count, then exactly one `Finished` or `Failed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadEvent {
+    Started { total: u64 },
+    Progress { downloaded: u64 },
+    Finished,
+    Failed(String),
+}
+
+/// What a single HEAD request reveals about a URL.
+pub struct HeadInfo {
+    pub content_length: u64,
+    pub accepts_ranges: bool,
+    pub content_type: Option<String>,
+}
+
 #[derive(Default)]
 pub struct Downloader {
     client: Client,
+    max_rate: Option<u64>,
+    per_connection_rate: Option<u64>,
+    chunk_size: Option<u64>,
+    byte_range: Option<(u64, u64)>,
+    retries: u64,
+    idle_timeout: Option<Duration>,
+    multi_progress: Option<Arc<MultiProgress>>,
+    progress_bar: Option<ProgressBar>,
+    progress_tx: Option<tokio::sync::mpsc::UnboundedSender<DownloadEvent>>,
+    quiet: bool,
 }
 
 impl Downloader {
-    /// Checks if downloading url accepts content-range header
-    pub async fn is_accept_ranges(&self, url: &str) -> Result<bool> {
-        let response = self.client.head(url).send().await?;
-        let header = response.headers().get(ACCEPT_RANGES);
-        Ok(matches!(header, Some(value) if value == "bytes"))
+    /// Caps the aggregate download speed in bytes per second.
+    pub fn with_max_rate(mut self, max_rate: Option<u64>) -> Self {
+        self.max_rate = max_rate;
+        self
+    }
+
+    /// Caps each connection's speed in bytes per second, independently of the
+    /// aggregate cap. When both are set each transfer obeys both buckets, so
+    /// the more restrictive one wins.
+    pub fn with_per_connection_rate(mut self, per_connection_rate: Option<u64>) -> Self {
+        self.per_connection_rate = per_connection_rate;
+        self
+    }
+
+    /// Fixes the size of each downloaded chunk instead of deriving it from
+    /// the thread count; `threads` then only limits concurrency.
+    pub fn with_chunk_size(mut self, chunk_size: Option<u64>) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Restricts the transfer to a half-open `[start, end)` byte window of
+    /// the remote file, for sampling instead of fetching everything. Needs a
+    /// server that honors ranged requests.
+    pub fn with_byte_range(mut self, byte_range: Option<(u64, u64)>) -> Self {
+        self.byte_range = byte_range;
+        self
+    }
+
+    /// Hides the progress bar and logs periodic percentage lines instead,
+    /// for non-interactive runs where bar redraws would garble the output.
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Replaces the default HTTP client, e.g. with one configured to use a
+    /// proxy.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Registers this download's progress bar into a shared group so several
+    /// concurrent downloads do not clobber each other's terminal output.
+    pub fn with_multi_progress(mut self, multi_progress: Option<Arc<MultiProgress>>) -> Self {
+        self.multi_progress = multi_progress;
+        self
+    }
+
+    /// Draws onto a caller-supplied bar (already registered wherever the
+    /// caller wants it) instead of creating one internally.
+    #[allow(dead_code)]
+    pub fn with_progress_bar(mut self, progress_bar: Option<ProgressBar>) -> Self {
+        self.progress_bar = progress_bar;
+        self
+    }
+
+    /// Sends [`DownloadEvent`]s over the channel as the transfer progresses,
+    /// in addition to (or, with `with_quiet`, instead of) drawing the bar.
+    pub fn with_progress_events(
+        mut self,
+        progress_tx: Option<tokio::sync::mpsc::UnboundedSender<DownloadEvent>>,
+    ) -> Self {
+        self.progress_tx = progress_tx;
+        self
+    }
+
+    /// A send can only fail when the receiver is gone, which is the
+    /// embedder's way of saying it stopped listening.
+    fn emit(&self, event: DownloadEvent) {
+        if let Some(progress_tx) = &self.progress_tx {
+            let _ = progress_tx.send(event);
+        }
+    }
+
+    /// Number of times a failed or stalled chunk is re-requested before the
+    /// download as a whole gives up.
+    pub fn with_retries(mut self, retries: u64) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Fails a chunk when no data arrives for this long, so a stalled
+    /// connection is retried instead of hanging forever.
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// One HEAD request answering both questions a download needs: how big
+    /// the file is and whether the server honors ranged requests.
+    pub async fn probe(&self, url: &str) -> Result<HeadInfo> {
+        let response = crate::utils::send_traced(&self.client, self.client.head(url)).await?;
+
+        let content_length = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .ok_or_else(|| anyhow!("Failed to get content length from '{}'", &url))?
+            .to_str()?
+            .parse()?;
+
+        let accepts_ranges =
+            matches!(response.headers().get(ACCEPT_RANGES), Some(value) if value == "bytes");
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        Ok(HeadInfo {
+            content_length,
+            accepts_ranges,
+            content_type,
+        })
     }
 
-    /// Download file at url and save to save_to path
+    /// Download file at url and save to save_to path. Returns the number of
+    /// bytes transferred so callers can aggregate totals.
     pub async fn download_to(
         &self,
         url: &str,
         title: &str,
         save_to: PathBuf,
         threads: u64,
-    ) -> Result<()> {
-        let total_size = self
-            .client
-            .head(url)
-            .send()
-            .await?
-            .headers()
-            .get(CONTENT_LENGTH)
-            .ok_or_else(|| anyhow!("Failed to get content length from '{}'", &url))?
-            .to_str()?
-            .parse::<u64>()?;
+    ) -> Result<u64> {
+        let result = self.run_download(url, title, save_to, threads).await;
+
+        match &result {
+            Ok(_) => self.emit(DownloadEvent::Finished),
+            Err(err) => self.emit(DownloadEvent::Failed(err.to_string())),
+        }
+
+        result
+    }
+
+    async fn run_download(
+        &self,
+        url: &str,
+        title: &str,
+        save_to: PathBuf,
+        threads: u64,
+    ) -> Result<u64> {
+        // A playlist is not one ranged file; it takes the segment path.
+        // Extension-less playlist URLs are caught below by content type.
+        // Segment counts, not byte sizes, drive HLS concurrency, so `auto`
+        // resolves against an unbounded size there: the cap alone decides.
+        if hls::is_hls_url(url) {
+            let threads = resolve_auto_threads(threads, u64::MAX);
+            return self.download_hls(url, title, &save_to, threads).await;
+        }
+
+        let head = self.probe(url).await?;
 
-        let progress = ProgressBar::new(0);
-        let draw_target = ProgressDrawTarget::stdout_with_hz(10);
+        if head
+            .content_type
+            .as_deref()
+            .is_some_and(hls::is_hls_content_type)
+        {
+            let threads = resolve_auto_threads(threads, u64::MAX);
+            return self.download_hls(url, title, &save_to, threads).await;
+        }
+
+        // A byte window caps what everything downstream sees: chunking, the
+        // progress bar, and the final size check all work in window-relative
+        // offsets, with `range_start` added back on the wire.
+        let (range_start, total_size) = match self.byte_range {
+            Some((start, end)) => {
+                let end = end.min(head.content_length);
+                if start >= end {
+                    return Err(anyhow!(
+                        "requested range {}-{} is empty for a {}-byte file",
+                        start,
+                        end,
+                        head.content_length
+                    ));
+                }
+                if !head.accepts_ranges {
+                    return Err(anyhow!(
+                        "'{}' does not support ranged requests, so partial downloads are unavailable",
+                        url
+                    ));
+                }
+
+                (start, end - start)
+            }
+            None => (0, head.content_length),
+        };
+
+        let threads = resolve_auto_threads(threads, total_size);
+
+        self.emit(DownloadEvent::Started { total: total_size });
+
+        let progress = match &self.progress_bar {
+            // The caller owns the bar and has already registered it.
+            Some(progress) => progress.clone(),
+            None if self.quiet => ProgressBar::hidden(),
+            None => {
+                let progress = ProgressBar::new(0);
+
+                match &self.multi_progress {
+                    Some(multi) => {
+                        multi.add(progress.clone());
+                    }
+                    None => progress.set_draw_target(ProgressDrawTarget::stdout_with_hz(10)),
+                }
 
-        progress.set_draw_target(draw_target);
+                progress
+            }
+        };
         progress.set_style(ProgressStyle::default_bar()
                 .template("{spinner:.dim} {wide_bar:.cyan/blue} {percent:.bold}% {bytes}/{total_bytes} ({binary_bytes_per_sec:.bold.dim} elapsed: {elapsed:.bold.dim} eta: {eta:.bold.dim})")
                 .tick_strings(&[
@@ -61,61 +277,954 @@ impl Downloader {
         progress.set_length(total_size);
         progress.set_message(title.to_owned());
 
-        if !self.is_accept_ranges(url).await? {
+        let percent_logger = self.quiet.then(|| {
+            let progress = progress.clone();
+            let title = title.to_owned();
+
+            tokio::spawn(async move {
+                let mut last_percent = 0;
+
+                loop {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+
+                    if progress.is_finished() {
+                        break;
+                    }
+
+                    let percent = match progress.length() {
+                        0 => 0,
+                        length => progress.position() * 100 / length,
+                    };
+
+                    if percent != last_percent {
+                        log::info!("'{}': {}%", title, percent);
+                        last_percent = percent;
+                    }
+                }
+            })
+        });
+
+        let part_path = append_extension(&save_to, ".part");
+        let manifest_path = append_extension(&save_to, ".part.resume");
+
+        let started = std::time::Instant::now();
+
+        if head.accepts_ranges {
+            self.download_chunked(
+                url,
+                &part_path,
+                &manifest_path,
+                range_start,
+                total_size,
+                threads,
+                &progress,
+            )
+            .await?;
+        } else {
+            log::info!("server does not support ranged requests, using a single stream");
+            self.download_sequential(url, &part_path, &progress).await?;
+        }
+
+        progress.finish_and_clear();
+        if let Some(logger) = percent_logger {
+            logger.abort();
+        }
+
+        // Servers sometimes serve fewer (or more) bytes than HEAD advertised;
+        // a silent short file is worse than a failed download.
+        let actual_size = std::fs::metadata(&part_path)?.len();
+        if actual_size != total_size {
+            log::error!(
+                "'{}': downloaded {} bytes but the server advertised {}",
+                url,
+                actual_size,
+                total_size
+            );
+            let _ = std::fs::remove_file(&part_path);
+            let _ = std::fs::remove_file(&manifest_path);
+
             return Err(anyhow!(
-                "Couldn't download file. Server doesn't support RANGE header!"
+                "downloaded size {} does not match advertised size {}",
+                actual_size,
+                total_size
             ));
         }
 
-        let chunk_size = total_size / threads;
-        let mut start = 0;
-        let mut ranges = vec![];
-        while start < total_size {
-            ranges.push((start, (start + chunk_size).min(total_size)));
-            start += chunk_size + 1;
-        }
+        std::fs::rename(&part_path, &save_to)?;
+        let _ = std::fs::remove_file(&manifest_path);
+
+        println!("{}", summary_line(title, total_size, started.elapsed()));
+
+        Ok(total_size)
+    }
+
+    /// Parallel ranged download, resumable via the sidecar manifest. Offsets
+    /// are relative to `range_start`, which is only added back onto the wire.
+    #[allow(clippy::too_many_arguments)]
+    async fn download_chunked(
+        &self,
+        url: &str,
+        part_path: &Path,
+        manifest_path: &Path,
+        range_start: u64,
+        total_size: u64,
+        threads: u64,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        let manifest = ResumeManifest::load(manifest_path, url, range_start)
+            .filter(|m| m.total_size == total_size)
+            .unwrap_or_else(|| ResumeManifest::new(url, range_start, total_size));
+
+        progress.set_position(manifest.completed_bytes());
+
+        let chunk_size = self
+            .chunk_size
+            .unwrap_or_else(|| total_size / threads.max(1));
+
+        // Indexes are positions in the full chunk list, so a resumed run logs
+        // the same chunk numbers as the run that left the manifest behind.
+        let pending: Vec<_> = chunk_ranges(total_size, chunk_size)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, (start, end))| !manifest.is_complete(*start, *end))
+            .collect();
+
+        // Persist right away so a run aborted before any chunk completes
+        // still leaves a manifest for the next attempt to resume from.
+        manifest.save(manifest_path)?;
+
+        let manifest = Arc::new(Mutex::new(manifest));
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(threads.max(1) as usize));
+        let limiter = self.max_rate.map(|rate| Arc::new(RateLimiter::new(rate)));
+
+        // First chunk to fail for good flips this, so siblings stop streaming
+        // instead of burning bandwidth on a download that is already lost.
+        let (cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
+        let cancel_tx = Arc::new(cancel_tx);
 
         let mut promises: Vec<JoinHandle<Result<()>>> = vec![];
-        let f = File::create(save_to.clone())?;
-        let file = Arc::new(Mutex::new(f));
+        let f = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(part_path)?;
+        // Workers use positioned writes into disjoint ranges, so the file
+        // needs no mutex and writers never contend on a shared cursor.
+        let file = Arc::new(f);
+
+        let retries = self.retries;
+        let idle_timeout = self.idle_timeout;
+        let per_connection_rate = self.per_connection_rate;
 
-        for (_idx, (start, end)) in ranges.into_iter().enumerate() {
+        for (index, (start, end)) in pending {
             let url = url.to_owned();
+            let client = self.client.clone();
+            let progress_tx = self.progress_tx.clone();
             let file = file.clone();
+            let manifest = manifest.clone();
+            let manifest_path = manifest_path.to_owned();
+            let limiter = limiter.clone();
+            let semaphore = semaphore.clone();
 
             let progress = progress.clone();
 
+            let cancel_tx = cancel_tx.clone();
+            let mut cancel_rx = cancel_rx.clone();
+
             promises.push(tokio::task::spawn(async move {
-                let mut headers = HeaderMap::new();
-                let range = format!("bytes={0}-{1}", start, end);
-                headers.insert(RANGE, HeaderValue::from_str(&range).unwrap());
+                let _permit = semaphore.acquire_owned().await?;
 
-                let client = reqwest::Client::builder()
-                    .default_headers(headers.clone())
-                    .build()?;
+                let work = async {
+                    let mut offset = start;
+                    let mut attempt = 0;
 
-                let response = client.get(url).send().await?;
+                    loop {
+                        let result = fetch_range(
+                            &client,
+                            &url,
+                            range_start,
+                            &mut offset,
+                            end,
+                            file.as_ref(),
+                            &progress,
+                            &progress_tx,
+                            &limiter,
+                            per_connection_rate,
+                            idle_timeout,
+                        )
+                        .await;
 
-                let mut stream = response.bytes_stream();
-                let mut offset = start;
+                        match result {
+                            Ok(()) => break,
+                            Err(err) if attempt < retries => {
+                                attempt += 1;
+                                log::warn!(
+                                    "chunk {} at byte {}: {}; retrying ({}/{})",
+                                    index,
+                                    offset,
+                                    err,
+                                    attempt,
+                                    retries
+                                );
+                            }
+                            Err(err) => {
+                                log::debug!(
+                                    "chunk {} at byte {}: giving up after {} retries: {}",
+                                    index,
+                                    offset,
+                                    retries,
+                                    err
+                                );
+                                return Err(err);
+                            }
+                        }
+                    }
 
-                while let Some(item) = stream.next().await {
-                    let chunk = item?;
-                    let mut f = file.lock().unwrap();
-                    f.seek(std::io::SeekFrom::Start(offset))?;
-                    f.write_all(&chunk)?;
+                    log::trace!("chunk {} ({}-{}) complete", index, start, end);
 
-                    offset += chunk.len() as u64;
-                    progress.inc(chunk.len() as u64);
-                }
+                    let mut manifest = manifest.lock().unwrap();
+                    manifest.mark_complete(start, end);
+                    manifest.save(&manifest_path)?;
+
+                    Ok(())
+                };
 
-                Ok(())
+                tokio::select! {
+                    // A sibling already failed; return quietly so its error is
+                    // the one surfaced to the caller.
+                    _ = cancel_rx.changed() => Ok(()),
+                    result = work => {
+                        if result.is_err() {
+                            let _ = cancel_tx.send(true);
+                        }
+                        result
+                    }
+                }
             }));
         }
 
-        try_join_all(promises).await?;
-        progress.finish_and_clear();
+        for result in try_join_all(promises).await? {
+            result?;
+        }
 
         Ok(())
     }
+
+    /// Streams the whole body over one connection for servers without RANGE
+    /// support. Not resumable, so the part file starts from scratch.
+    async fn download_sequential(
+        &self,
+        url: &str,
+        part_path: &Path,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        let limiter = self.max_rate.map(RateLimiter::new);
+        // With a single connection both caps behave identically, but applying
+        // both keeps the more restrictive one in charge.
+        let per_connection = self.per_connection_rate.map(RateLimiter::new);
+
+        let mut file = std::fs::File::create(part_path)?;
+        let response = crate::utils::send_traced(&self.client, self.client.get(url)).await?;
+
+        let mut stream = response.bytes_stream();
+        while let Some(item) = stream.next().await {
+            let chunk = item?;
+            file.write_all(&chunk)?;
+            progress.inc(chunk.len() as u64);
+            self.emit(DownloadEvent::Progress {
+                downloaded: progress.position(),
+            });
+
+            if let Some(limiter) = &limiter {
+                limiter.throttle(chunk.len() as u64).await;
+            }
+
+            if let Some(per_connection) = &per_connection {
+                per_connection.throttle(chunk.len() as u64).await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// One-line completion summary: filename, final size, elapsed time and
+/// average rate, printed once the bar has been cleared.
+fn summary_line(title: &str, bytes: u64, elapsed: Duration) -> String {
+    format!(
+        "'{}': {} in {:.1?} ({}/s)",
+        title,
+        humansize::format_size(bytes, humansize::DECIMAL),
+        elapsed,
+        humansize::format_size(average_rate(bytes, elapsed), humansize::DECIMAL)
+    )
+}
+
+/// Average transfer rate in bytes per second. A zero elapsed time yields the
+/// byte count itself instead of dividing by zero.
+pub fn average_rate(bytes: u64, elapsed: Duration) -> u64 {
+    if elapsed.is_zero() {
+        return bytes;
+    }
+
+    (bytes as f64 / elapsed.as_secs_f64()) as u64
+}
+
+/// Streams one byte range into the shared file starting at `*offset`,
+/// advancing it as data lands so a retry after a mid-chunk failure resumes
+/// from the last written byte rather than the chunk start.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_range(
+    client: &Client,
+    url: &str,
+    range_start: u64,
+    offset: &mut u64,
+    end: u64,
+    file: &std::fs::File,
+    progress: &ProgressBar,
+    progress_tx: &Option<tokio::sync::mpsc::UnboundedSender<DownloadEvent>>,
+    limiter: &Option<Arc<RateLimiter>>,
+    per_connection_rate: Option<u64>,
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
+    // A private bucket for this connection, unlike the shared aggregate
+    // limiter all workers drain together.
+    let per_connection = per_connection_rate.map(RateLimiter::new);
+
+    let response = crate::utils::send_traced(
+        client,
+        range_request(client, url, range_start + *offset, range_start + end),
+    )
+    .await?;
+    let mut stream = response.bytes_stream();
+
+    loop {
+        let item = match idle_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, stream.next())
+                .await
+                .map_err(|_| {
+                    anyhow!(
+                        "no data for {:?} while fetching bytes {}-{}",
+                        timeout,
+                        offset,
+                        end
+                    )
+                })?,
+            None => stream.next().await,
+        };
+
+        let chunk = match item {
+            Some(chunk) => chunk?,
+            None => break,
+        };
+
+        write_all_at(file, &chunk, *offset)?;
+
+        *offset += chunk.len() as u64;
+        progress.inc(chunk.len() as u64);
+
+        // The bar position is shared by every worker, so it already is the
+        // cumulative count the event promises.
+        if let Some(progress_tx) = progress_tx {
+            let _ = progress_tx.send(DownloadEvent::Progress {
+                downloaded: progress.position(),
+            });
+        }
+
+        if let Some(limiter) = limiter {
+            limiter.throttle(chunk.len() as u64).await;
+        }
+
+        if let Some(per_connection) = &per_connection {
+            per_connection.throttle(chunk.len() as u64).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write at an absolute offset without touching the file cursor, so workers
+/// streaming disjoint ranges need no synchronization between them.
+fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.write_all_at(buf, offset)
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+
+        let mut written = 0;
+        while written < buf.len() {
+            match file.seek_write(&buf[written..], offset + written as u64)? {
+                0 => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    ))
+                }
+                n => written += n,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// GET request for one inclusive byte range, carrying the `Range` header
+/// per-request so a single pooled client can serve every chunk.
+fn range_request(client: &Client, url: &str, start: u64, end: u64) -> reqwest::RequestBuilder {
+    client.get(url).header(RANGE, format!("bytes={}-{}", start, end))
+}
+
+/// Resolves the `--threads auto` marker against the now-known payload size;
+/// explicit counts pass through untouched. The cap is the machine's
+/// parallelism, itself bounded by [`crate::utils::MAX_THREADS`].
+fn resolve_auto_threads(threads: u64, total_size: u64) -> u64 {
+    if threads != crate::utils::AUTO_THREADS {
+        return threads;
+    }
+
+    let cap = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(crate::utils::MAX_THREADS as usize);
+
+    let resolved = crate::utils::auto_threads(total_size, cap);
+    log::debug!("--threads auto resolved to {}", resolved);
+    resolved
+}
+
+/// Appends a suffix to the full file name, keeping the original extension
+/// (`movie.mp4` -> `movie.mp4.part`).
+fn append_extension(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Splits `total_size` bytes into contiguous inclusive `(start, end)` ranges
+/// of `chunk_size` bytes each (the last one may be shorter), suitable for
+/// `Range: bytes={start}-{end}` headers. Every byte in `0..total_size` is
+/// covered exactly once.
+fn chunk_ranges(total_size: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    let chunk_size = chunk_size.max(1);
+
+    let mut ranges = vec![];
+    let mut start = 0;
+    while start < total_size {
+        let end = (start + chunk_size).min(total_size);
+        ranges.push((start, end - 1));
+        start = end;
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{average_rate, chunk_ranges, range_request, summary_line, write_all_at, Downloader};
+    use crate::test_util::FileServer;
+
+    #[tokio::test]
+    async fn a_download_issues_exactly_one_head_probe() {
+        let content = vec![5u8; 50_000];
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        Downloader::default()
+            .with_quiet(true)
+            .download_to(&server.url, "file.bin", save_to.clone(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(server.head_requests(), 1);
+        assert_eq!(std::fs::read(save_to).unwrap(), content);
+    }
+
+    #[test]
+    fn summary_figures_are_computed_from_size_and_elapsed() {
+        let elapsed = std::time::Duration::from_secs(8);
+
+        assert_eq!(average_rate(10_000_000, elapsed), 1_250_000);
+        assert_eq!(average_rate(500, std::time::Duration::ZERO), 500);
+
+        let line = summary_line("movie.mp4", 10_000_000, elapsed);
+        assert!(line.contains("movie.mp4"), "got: {}", line);
+        assert!(line.contains("10 MB"), "got: {}", line);
+        assert!(line.contains("1.25 MB/s"), "got: {}", line);
+    }
+
+    #[tokio::test]
+    async fn zero_threads_does_not_panic_on_the_chunk_size_division() {
+        // Zero is the `--threads auto` marker these days, so a programmatic
+        // zero resolves to a real count before the chunk-size division.
+        let content = vec![3u8; 10_000];
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        Downloader::default()
+            .with_quiet(true)
+            .download_to(&server.url, "file.bin", save_to.clone(), 0)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(save_to).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn a_failing_chunk_cancels_its_siblings_promptly() {
+        let server = FileServer::start_with_get_delay(
+            vec![7u8; 100_000],
+            true,
+            std::time::Duration::from_secs(3),
+        )
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        let downloader = Downloader::default()
+            .with_chunk_size(Some(10_000))
+            .with_quiet(true);
+
+        let started = std::time::Instant::now();
+        let result = downloader.download_to(&server.url, "file.bin", save_to, 4).await;
+
+        assert!(result.is_err());
+        // Healthy siblings stall for seconds; finishing well before that
+        // proves they were cancelled rather than drained.
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(2),
+            "siblings were not cancelled promptly"
+        );
+    }
+
+    #[tokio::test]
+    async fn concurrent_positioned_writes_produce_a_byte_correct_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.bin");
+
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let file = std::sync::Arc::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&path)
+                .unwrap(),
+        );
+
+        // Odd chunk size so ranges land on awkward boundaries.
+        let mut handles = vec![];
+        for (start, end) in chunk_ranges(content.len() as u64, 7_919) {
+            let file = file.clone();
+            let slice = content[start as usize..=end as usize].to_vec();
+
+            handles.push(tokio::spawn(async move {
+                write_all_at(&file, &slice, start).unwrap();
+            }));
+        }
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(std::fs::read(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn range_request_sets_the_range_header() {
+        let client = reqwest::Client::new();
+        let request = range_request(&client, "http://example.com/file.bin", 100, 199)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            request.headers().get(reqwest::header::RANGE).unwrap(),
+            "bytes=100-199"
+        );
+    }
+
+    fn assert_covers_exactly(total_size: u64, chunk_size: u64) {
+        let ranges = chunk_ranges(total_size, chunk_size);
+
+        let mut expected_start = 0;
+        for (start, end) in &ranges {
+            assert_eq!(*start, expected_start, "gap or overlap before byte {}", start);
+            assert!(end >= start);
+            assert!(*end < total_size, "range end {} past last byte", end);
+            expected_start = end + 1;
+        }
+
+        assert_eq!(expected_start, total_size, "ranges do not cover the full size");
+    }
+
+    #[test]
+    fn ranges_cover_every_byte_exactly_once() {
+        for total_size in [1, 2, 7, 100, 1000, 1001, 1023, 4096, 999_999] {
+            for chunk_size in [1, 3, 16, 250, 1000, 4096] {
+                assert_covers_exactly(total_size, chunk_size);
+            }
+        }
+    }
+
+    #[test]
+    fn size_not_divisible_by_chunk_size() {
+        let ranges = chunk_ranges(10, 4);
+        assert_eq!(ranges, vec![(0, 3), (4, 7), (8, 9)]);
+    }
+
+    #[test]
+    fn chunk_larger_than_file_yields_one_range() {
+        let ranges = chunk_ranges(3, 8);
+        assert_eq!(ranges, vec![(0, 2)]);
+    }
+
+    #[test]
+    fn empty_file_produces_no_ranges() {
+        assert!(chunk_ranges(0, 4).is_empty());
+    }
+
+    #[tokio::test]
+    async fn in_flight_chunks_never_exceed_the_thread_count() {
+        let content = vec![7u8; 20_000];
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        // 500-byte chunks produce 40 ranges, far more than the 3 permitted
+        // concurrent transfers.
+        Downloader::default()
+            .with_chunk_size(Some(500))
+            .download_to(&server.url, "file.bin", save_to.clone(), 3)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+        assert!(server.peak_concurrent_gets() <= 3);
+    }
+
+    #[tokio::test]
+    async fn quiet_mode_downloads_without_drawing_a_bar() {
+        let content = vec![4u8; 10_000];
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        Downloader::default()
+            .with_quiet(true)
+            .download_to(&server.url, "file.bin", save_to.clone(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn detects_a_body_shorter_than_the_advertised_size() {
+        let content = vec![9u8; 5_000];
+        let server = FileServer::start_with_advertised_size(content, 8_000).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        let err = Downloader::default()
+            .download_to(&server.url, "file.bin", save_to.clone(), 2)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("does not match"), "got: {}", err);
+        assert!(!save_to.exists());
+        assert!(!dir.path().join("file.bin.part").exists());
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_single_stream_without_range_support() {
+        let content: Vec<u8> = (0..50_000u32).map(|i| (i % 13) as u8).collect();
+        let server = FileServer::start_without_ranges(content.clone()).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        Downloader::default()
+            .download_to(&server.url, "file.bin", save_to.clone(), 4)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+        assert!(!dir.path().join("file.bin.part").exists());
+    }
+
+    #[tokio::test]
+    async fn shared_multi_progress_bars_both_reach_completion() {
+        use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget};
+
+        let first = FileServer::start(vec![1u8; 10_000], false).await;
+        let second = FileServer::start(vec![2u8; 20_000], false).await;
+
+        let multi = MultiProgress::new();
+        multi.set_draw_target(ProgressDrawTarget::hidden());
+
+        let first_bar = multi.add(ProgressBar::new(0));
+        let second_bar = multi.add(ProgressBar::new(0));
+
+        let dir = tempfile::tempdir().unwrap();
+
+        let first_downloader = Downloader::default().with_progress_bar(Some(first_bar.clone()));
+        let second_downloader = Downloader::default().with_progress_bar(Some(second_bar.clone()));
+
+        let (first_result, second_result) = tokio::join!(
+            first_downloader.download_to(&first.url, "a.bin", dir.path().join("a.bin"), 2),
+            second_downloader.download_to(&second.url, "b.bin", dir.path().join("b.bin"), 2),
+        );
+
+        first_result.unwrap();
+        second_result.unwrap();
+
+        assert_eq!(first_bar.position(), 10_000);
+        assert_eq!(second_bar.position(), 20_000);
+    }
+
+    #[tokio::test]
+    async fn retries_complete_a_chunk_the_server_dropped() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        // The server sends half of the first requested chunk and drops the
+        // connection; one retry must finish the range in the same run.
+        let server = FileServer::start(content.clone(), true).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        Downloader::default()
+            .with_retries(1)
+            .download_to(&server.url, "file.bin", save_to.clone(), 4)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn a_byte_window_downloads_only_that_slice() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        let bytes = Downloader::default()
+            .with_byte_range(Some((500, 1_500)))
+            .download_to(&server.url, "file.bin", save_to.clone(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, 1_000);
+        assert_eq!(std::fs::read(&save_to).unwrap(), content[500..1_500]);
+    }
+
+    #[tokio::test]
+    async fn a_head_cap_larger_than_the_file_downloads_everything() {
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        // --head is a (0, limit) window; a limit beyond the end clamps to it.
+        let bytes = Downloader::default()
+            .with_byte_range(Some((0, 1_000_000)))
+            .download_to(&server.url, "file.bin", save_to.clone(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, content.len() as u64);
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn download_events_arrive_in_order_on_the_channel() {
+        use super::DownloadEvent;
+
+        let content: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        Downloader::default()
+            .with_quiet(true)
+            .with_progress_events(Some(tx))
+            .download_to(&server.url, "file.bin", save_to, 2)
+            .await
+            .unwrap();
+
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+
+        assert_eq!(
+            events.first(),
+            Some(&DownloadEvent::Started {
+                total: content.len() as u64
+            })
+        );
+        assert_eq!(events.last(), Some(&DownloadEvent::Finished));
+
+        // Everything in between reports cumulative progress, ending at the
+        // full size.
+        let progress: Vec<u64> = events[1..events.len() - 1]
+            .iter()
+            .map(|event| match event {
+                DownloadEvent::Progress { downloaded } => *downloaded,
+                other => panic!("unexpected event in the middle: {:?}", other),
+            })
+            .collect();
+        assert!(!progress.is_empty());
+        assert_eq!(progress.last(), Some(&(content.len() as u64)));
+
+        // A failing download ends with `Failed` instead.
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        Downloader::default()
+            .with_quiet(true)
+            .with_progress_events(Some(tx))
+            .download_to(
+                "http://127.0.0.1:9/file.bin",
+                "file.bin",
+                dir.path().join("missing.bin"),
+                2,
+            )
+            .await
+            .unwrap_err();
+
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        assert!(matches!(events.last(), Some(DownloadEvent::Failed(_))));
+    }
+
+    #[tokio::test]
+    async fn custom_pool_settings_still_download() {
+        let content: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), false).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        let mut config = crate::api::Config::default();
+        config.set_pool_tuning(Some(1), Some(5));
+
+        let bytes = Downloader::default()
+            .with_client(config.http_client().unwrap())
+            .download_to(&server.url, "file.bin", save_to.clone(), 4)
+            .await
+            .unwrap();
+
+        assert_eq!(bytes, content.len() as u64);
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn a_byte_window_needs_range_support() {
+        let content = vec![0u8; 1_000];
+        let server = FileServer::start_without_ranges(content).await;
+
+        let dir = tempfile::tempdir().unwrap();
+
+        let err = Downloader::default()
+            .with_byte_range(Some((0, 100)))
+            .download_to(&server.url, "file.bin", dir.path().join("file.bin"), 2)
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("partial downloads"));
+    }
+
+    #[tokio::test]
+    async fn chunk_retries_are_logged_with_their_index() {
+        static CAPTURED: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+        struct CaptureLogger;
+
+        impl log::Log for CaptureLogger {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                CAPTURED.lock().unwrap().push(record.args().to_string());
+            }
+
+            fn flush(&self) {}
+        }
+
+        static LOGGER: CaptureLogger = CaptureLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), true).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        // One thread means one chunk, so the dropped connection is
+        // deterministically chunk 0.
+        Downloader::default()
+            .with_retries(1)
+            .download_to(&server.url, "file.bin", save_to.clone(), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+
+        let captured = CAPTURED.lock().unwrap();
+        assert!(captured
+            .iter()
+            .any(|line| line.contains("chunk 0 at byte") && line.contains("retrying (1/1)")));
+        assert!(captured
+            .iter()
+            .any(|line| line.starts_with("chunk 0 (") && line.ends_with("complete")));
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_download_from_part_file() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let server = FileServer::start(content.clone(), true).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let save_to = dir.path().join("file.bin");
+
+        // First run: the server drops one chunk midway, so the download
+        // fails and leaves a .part file plus a resume manifest behind.
+        let result = Downloader::default()
+            .download_to(&server.url, "file.bin", save_to.clone(), 4)
+            .await;
+        assert!(result.is_err());
+        assert!(!save_to.exists());
+        assert!(dir.path().join("file.bin.part").exists());
+        assert!(dir.path().join("file.bin.part.resume").exists());
+
+        // Second run completes the remaining ranges and renames the file.
+        Downloader::default()
+            .download_to(&server.url, "file.bin", save_to.clone(), 4)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&save_to).unwrap(), content);
+        assert!(!dir.path().join("file.bin.part").exists());
+        assert!(!dir.path().join("file.bin.part.resume").exists());
+    }
 }
@@ -0,0 +1,110 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token-bucket limiter. One instance shared by all chunk workers caps the
+/// aggregate download rate; a private instance per connection caps a single
+/// stream instead. The bucket holds up to one second of burst.
+pub struct RateLimiter {
+    rate: f64,
+    capacity: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second as f64;
+
+        Self {
+            rate,
+            capacity: rate,
+            bucket: Mutex::new(Bucket {
+                available: rate,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Accounts for `amount` transferred bytes, sleeping long enough to keep
+    /// the overall rate under the cap.
+    pub async fn throttle(&self, amount: u64) {
+        let wait = self.consume_at(amount, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Takes `amount` tokens from the bucket as of `now`, returning how long
+    /// the caller must pause. Separated from the clock for testability.
+    fn consume_at(&self, amount: u64, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let elapsed = now
+            .saturating_duration_since(bucket.last_refill)
+            .as_secs_f64();
+        bucket.available = (bucket.available + elapsed * self.rate).min(self.capacity);
+        bucket.last_refill = now;
+
+        bucket.available -= amount as f64;
+
+        if bucket.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.available / self.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[test]
+    fn allows_bursting_up_to_one_second_of_rate() {
+        let limiter = RateLimiter::new(1000);
+        let now = Instant::now();
+
+        assert_eq!(limiter.consume_at(1000, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn delays_once_the_bucket_is_drained() {
+        let limiter = RateLimiter::new(1000);
+        let now = Instant::now();
+
+        limiter.consume_at(1000, now);
+        let wait = limiter.consume_at(500, now);
+
+        assert_eq!(wait, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn per_connection_limiters_keep_independent_buckets() {
+        // One limiter per connection: one stream draining its bucket must
+        // not slow down another.
+        let first = RateLimiter::new(1000);
+        let second = RateLimiter::new(1000);
+        let now = Instant::now();
+
+        first.consume_at(1000, now);
+        assert_eq!(first.consume_at(250, now), Duration::from_millis(250));
+        assert_eq!(second.consume_at(1000, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn refills_with_elapsed_time() {
+        let limiter = RateLimiter::new(1000);
+        let now = Instant::now();
+
+        limiter.consume_at(1000, now);
+        let wait = limiter.consume_at(500, now + Duration::from_secs(2));
+
+        assert_eq!(wait, Duration::ZERO);
+    }
+}
@@ -0,0 +1,143 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+
+/// Selection of episode or season numbers parsed from the CLI: a single
+/// number ("5"), an inclusive range ("3-7"), a comma-separated list mixing
+/// both ("1,4,9-12"), or an explicit "all" matching every number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EpisodeSelector {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Single(usize),
+    Range(usize, usize),
+    All,
+}
+
+impl EpisodeSelector {
+    /// Selector matching exactly one number, for URL refs like `.../s5e3`.
+    pub fn single(number: usize) -> Self {
+        Self {
+            parts: vec![Part::Single(number)],
+        }
+    }
+
+    pub fn matches(&self, number: usize) -> bool {
+        self.parts.iter().any(|part| match part {
+            Part::Single(value) => *value == number,
+            Part::Range(from, to) => (*from..=*to).contains(&number),
+            Part::All => true,
+        })
+    }
+}
+
+impl FromStr for EpisodeSelector {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // Same as omitting the flag entirely, but scripts can be explicit.
+        if value.trim().eq_ignore_ascii_case("all") {
+            return Ok(Self {
+                parts: vec![Part::All],
+            });
+        }
+
+        let mut parts = vec![];
+
+        for piece in value.split(',') {
+            let piece = piece.trim();
+
+            if piece.is_empty() {
+                return Err(anyhow!("empty entry in selector '{}'", value));
+            }
+
+            if piece.eq_ignore_ascii_case("all") {
+                return Err(anyhow!(
+                    "'all' cannot be combined with numbers in selector '{}'",
+                    value
+                ));
+            }
+
+            if let Some((from, to)) = piece.split_once('-') {
+                let from: usize = parse_number(from.trim(), piece)?;
+                let to: usize = parse_number(to.trim(), piece)?;
+
+                if from > to {
+                    return Err(anyhow!("range '{}' is reversed", piece));
+                }
+
+                parts.push(Part::Range(from, to));
+            } else {
+                parts.push(Part::Single(parse_number(piece, piece)?));
+            }
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+fn parse_number(value: &str, context: &str) -> Result<usize, Error> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("'{}' is not a number in selector '{}'", value, context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EpisodeSelector;
+
+    fn selector(value: &str) -> EpisodeSelector {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_a_single_number() {
+        let selector = selector("5");
+        assert!(selector.matches(5));
+        assert!(!selector.matches(4));
+    }
+
+    #[test]
+    fn matches_an_inclusive_range() {
+        let selector = selector("3-7");
+        assert!(selector.matches(3));
+        assert!(selector.matches(7));
+        assert!(!selector.matches(2));
+        assert!(!selector.matches(8));
+    }
+
+    #[test]
+    fn matches_lists_mixing_numbers_and_ranges() {
+        let selector = selector("1,4,9-12");
+        assert!(selector.matches(1));
+        assert!(selector.matches(4));
+        assert!(selector.matches(10));
+        assert!(!selector.matches(2));
+        assert!(!selector.matches(13));
+    }
+
+    #[test]
+    fn all_matches_every_number() {
+        assert!(selector("all").matches(1));
+        assert!(selector("all").matches(999));
+        assert!(selector("ALL").matches(42));
+    }
+
+    #[test]
+    fn all_cannot_be_mixed_with_numbers() {
+        assert!("all,3".parse::<EpisodeSelector>().is_err());
+        assert!("1,all".parse::<EpisodeSelector>().is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        assert!("".parse::<EpisodeSelector>().is_err());
+        assert!("5-3".parse::<EpisodeSelector>().is_err());
+        assert!("abc".parse::<EpisodeSelector>().is_err());
+        assert!("1,,2".parse::<EpisodeSelector>().is_err());
+        assert!("1-".parse::<EpisodeSelector>().is_err());
+    }
+}
@@ -0,0 +1,469 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Minimal HTTP server for downloader tests: serves a fixed body, answers
+/// `HEAD` with `Content-Length`/`Accept-Ranges` and `GET` with optional
+/// `Range` support. When `fail_first_get` is set, the first `GET` sends only
+/// half of the requested bytes and drops the connection, simulating an
+/// interrupted transfer.
+pub struct FileServer {
+    pub url: String,
+    peak_gets: Arc<AtomicUsize>,
+    heads: Arc<AtomicUsize>,
+}
+
+impl FileServer {
+    pub async fn start(content: Vec<u8>, fail_first_get: bool) -> Self {
+        Self::spawn(content, fail_first_get, true, None, None).await
+    }
+
+    /// Variant that neither advertises nor honors `Range` requests, always
+    /// serving the whole body.
+    pub async fn start_without_ranges(content: Vec<u8>) -> Self {
+        Self::spawn(content, false, false, None, None).await
+    }
+
+    /// Variant whose `HEAD` advertises a different size than the body it
+    /// actually serves, like a misconfigured CDN. No range support, so the
+    /// whole (short) body streams over one connection.
+    pub async fn start_with_advertised_size(content: Vec<u8>, advertised_size: usize) -> Self {
+        Self::spawn(content, false, false, Some(advertised_size), None).await
+    }
+
+    /// Variant whose healthy `GET`s stall before sending their body while the
+    /// failing one (with `fail_first_get`) errors immediately, for observing
+    /// how quickly sibling transfers are abandoned.
+    pub async fn start_with_get_delay(
+        content: Vec<u8>,
+        fail_first_get: bool,
+        get_delay: std::time::Duration,
+    ) -> Self {
+        Self::spawn(content, fail_first_get, true, None, Some(get_delay)).await
+    }
+
+    async fn spawn(
+        content: Vec<u8>,
+        fail_first_get: bool,
+        ranges_supported: bool,
+        advertised_size: Option<usize>,
+        get_delay: Option<std::time::Duration>,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let content = Arc::new(content);
+        let already_failed = Arc::new(AtomicBool::new(!fail_first_get));
+        let peak_gets = Arc::new(AtomicUsize::new(0));
+        let heads = Arc::new(AtomicUsize::new(0));
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = peak_gets.clone();
+        let head_counter = heads.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                };
+
+                tokio::spawn(Self::handle(
+                    socket,
+                    content.clone(),
+                    already_failed.clone(),
+                    ranges_supported,
+                    advertised_size,
+                    get_delay,
+                    (in_flight.clone(), peak.clone()),
+                    head_counter.clone(),
+                ));
+            }
+        });
+
+        Self {
+            url: format!("http://{}/file.bin", addr),
+            peak_gets,
+            heads,
+        }
+    }
+
+    /// Highest number of GET requests that were in flight at the same time.
+    pub fn peak_concurrent_gets(&self) -> usize {
+        self.peak_gets.load(Ordering::SeqCst)
+    }
+
+    /// Total HEAD requests served.
+    pub fn head_requests(&self) -> usize {
+        self.heads.load(Ordering::SeqCst)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn handle(
+        mut socket: tokio::net::TcpStream,
+        content: Arc<Vec<u8>>,
+        already_failed: Arc<AtomicBool>,
+        ranges_supported: bool,
+        advertised_size: Option<usize>,
+        get_delay: Option<std::time::Duration>,
+        (in_flight, peak): (Arc<AtomicUsize>, Arc<AtomicUsize>),
+        heads: Arc<AtomicUsize>,
+    ) {
+        let mut head = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            head.extend_from_slice(&buf[..n]);
+            if head.windows(4).any(|w| w == b"\r\n\r\n") {
+                break;
+            }
+        }
+
+        let request = String::from_utf8_lossy(&head).into_owned();
+        let method = request.split_whitespace().next().unwrap_or("").to_owned();
+        let range = if !ranges_supported {
+            None
+        } else {
+            request.lines().find_map(|line| {
+            line.to_ascii_lowercase()
+                .strip_prefix("range: bytes=")
+                    .and_then(|r| r.split_once('-').map(|(s, e)| (s.to_owned(), e.to_owned())))
+            })
+        };
+
+        let (status, start, end) = match range {
+            Some((s, e)) => {
+                let start: usize = s.parse().unwrap_or(0);
+                let end: usize = e
+                    .parse::<usize>()
+                    .unwrap_or(content.len() - 1)
+                    .min(content.len() - 1);
+                ("206 Partial Content", start, end)
+            }
+            None => ("200 OK", 0, content.len() - 1),
+        };
+
+        let ranges_header = if ranges_supported {
+            "Accept-Ranges: bytes\r\n"
+        } else {
+            ""
+        };
+
+        if method == "HEAD" {
+            heads.fetch_add(1, Ordering::SeqCst);
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+                advertised_size.unwrap_or(content.len()),
+                ranges_header
+            );
+            socket.write_all(response.as_bytes()).await.ok();
+            return;
+        }
+
+        let concurrent = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(concurrent, Ordering::SeqCst);
+
+        let fail_this_get = !already_failed.swap(true, Ordering::SeqCst);
+
+        // Give sibling requests a chance to overlap so concurrency is
+        // actually observable; the failing GET never stalls so its error
+        // arrives first.
+        if !fail_this_get {
+            tokio::time::sleep(get_delay.unwrap_or(std::time::Duration::from_millis(10))).await;
+        }
+
+        let body = &content[start..=end];
+        let header = format!(
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+            status,
+            body.len(),
+            ranges_header
+        );
+        socket.write_all(header.as_bytes()).await.ok();
+
+        if fail_this_get {
+            socket.write_all(&body[..body.len() / 2]).await.ok();
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+
+        socket.write_all(body).await.ok();
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Serves a scripted sequence of `(status, body)` responses, one per request,
+/// repeating the last one once the script is exhausted. Counts the requests
+/// it has answered and keeps their raw heads for header assertions.
+pub struct StubServer {
+    pub url: String,
+    pub hits: Arc<std::sync::atomic::AtomicUsize>,
+    requests: Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl StubServer {
+    pub async fn start(responses: Vec<(u16, String)>) -> Self {
+        Self::start_with_delay(responses, std::time::Duration::ZERO).await
+    }
+
+    /// Like `start`, but waits before answering each request, for exercising
+    /// client-side timeouts.
+    pub async fn start_with_delay(
+        responses: Vec<(u16, String)>,
+        delay: std::time::Duration,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let hits = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let script = Arc::new(std::sync::Mutex::new(responses));
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let counter = hits.clone();
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                };
+
+                let mut head = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let n = match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    head.extend_from_slice(&buf[..n]);
+                    if head.windows(4).any(|w| w == b"\r\n\r\n") {
+                        break;
+                    }
+                }
+
+                counter.fetch_add(1, Ordering::SeqCst);
+                seen.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&head).into_owned());
+
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+
+                let (status, body) = {
+                    let mut script = script.lock().unwrap();
+                    if script.len() > 1 {
+                        script.remove(0)
+                    } else {
+                        script.first().cloned().unwrap_or((200, String::new()))
+                    }
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {} Scripted\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.ok();
+            }
+        });
+
+        Self {
+            url: format!("http://{}/", addr),
+            hits,
+            requests,
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    /// Raw request heads (request line plus headers) in arrival order.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// A mock kinopub API covering the endpoints `App`, `ApiClient`, and
+/// `Authenticator` touch: the OAuth device flow, `v1/user`, `v1/items/{id}`,
+/// `v1/items/search`, plus one static media file the item fixture points at.
+/// Device authorization is approved on the first poll, so a full
+/// authenticate-then-download run completes without interaction.
+pub struct MockKinopub {
+    pub url: String,
+    pub media_content: Vec<u8>,
+    requests: Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+impl MockKinopub {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let media_content: Vec<u8> = (0..16_384u32).map(|i| (i % 251) as u8).collect();
+        let media_url = format!("http://{}/media/movie.mp4", addr);
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let content = Arc::new(media_content.clone());
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                };
+
+                tokio::spawn(Self::handle(
+                    socket,
+                    content.clone(),
+                    media_url.clone(),
+                    seen.clone(),
+                ));
+            }
+        });
+
+        Self {
+            url: format!("http://{}/", addr),
+            media_content,
+            requests,
+        }
+    }
+
+    /// A `Config` pointed at this server, ready for `App::new`.
+    pub fn config(&self) -> crate::api::Config {
+        crate::api::Config {
+            api_url: self.url.clone(),
+            ..crate::api::Config::default()
+        }
+    }
+
+    /// Request lines (method and path) in arrival order.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    async fn handle(
+        mut socket: tokio::net::TcpStream,
+        media: Arc<Vec<u8>>,
+        media_url: String,
+        seen: Arc<std::sync::Mutex<Vec<String>>>,
+    ) {
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let header_end = loop {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => return,
+                Ok(n) => n,
+            };
+            raw.extend_from_slice(&buf[..n]);
+            if let Some(pos) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&raw[..header_end]).into_owned();
+        let content_length = head
+            .lines()
+            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length: ").map(str::to_owned))
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+
+        // Forms (the device-flow grant type) ride in the body; read it fully
+        // before routing.
+        while raw.len() < header_end + content_length {
+            let n = match socket.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            raw.extend_from_slice(&buf[..n]);
+        }
+        let body = String::from_utf8_lossy(&raw[header_end..]).into_owned();
+
+        let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+        let method = request_line.next().unwrap_or("").to_owned();
+        let path = request_line.next().unwrap_or("").to_owned();
+        seen.lock().unwrap().push(format!("{} {}", method, path));
+
+        if path.starts_with("/media/") {
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: video/mp4\r\nConnection: close\r\n\r\n",
+                media.len()
+            );
+            socket.write_all(header.as_bytes()).await.ok();
+            if method != "HEAD" {
+                socket.write_all(&media).await.ok();
+            }
+            return;
+        }
+
+        let json = if path.starts_with("/oauth2/device") {
+            if body.contains("grant_type=device_code") {
+                r#"{
+                    "code": "mock-code",
+                    "user_code": "MOCK",
+                    "verification_uri": "https://example.com/device",
+                    "expires_in": 300,
+                    "interval": 1
+                }"#
+                .to_owned()
+            } else {
+                // device_token polls and refresh_token exchanges both get a
+                // token straight away.
+                r#"{
+                    "access_token": "mock-access",
+                    "refresh_token": "mock-refresh",
+                    "expires_in": 3600
+                }"#
+                .to_owned()
+            }
+        } else if path.starts_with("/v1/device/notify") {
+            "{}".to_owned()
+        } else if path.starts_with("/v1/user") {
+            r#"{"user": {"username": "mockuser", "reg_date": 1600000000, "subscription": {}}}"#
+                .to_owned()
+        } else if path.starts_with("/v1/items/search") {
+            r#"{
+                "items": [{
+                    "id": 1,
+                    "title": "Mock Movie",
+                    "year": 2021,
+                    "type": "movie",
+                    "plot": "A mock movie.",
+                    "genres": []
+                }],
+                "pagination": {"total": 1}
+            }"#
+            .to_owned()
+        } else if path.starts_with("/v1/items/") {
+            format!(
+                r#"{{"item": {{
+                    "type": "movie",
+                    "title": "Mock Movie",
+                    "year": 2021,
+                    "plot": "A mock movie.",
+                    "videos": [{{"duration": 60, "files": [
+                        {{"quality": "720p", "url": {{"http": "{}"}}}}
+                    ]}}]
+                }}}}"#,
+                media_url
+            )
+        } else {
+            let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            socket.write_all(response.as_bytes()).await.ok();
+            return;
+        };
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n{}",
+            json.len(),
+            json
+        );
+        socket.write_all(response.as_bytes()).await.ok();
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use thiserror::Error;
 use url::Url;
 
@@ -39,36 +39,393 @@ impl StringExt for String {
     }
 }
 
+/// Parses an item reference from the CLI: either a bare numeric id or a full
+/// kino.pub URL like `https://kino.pub/item/view/12345/s5e3`, whose trailing
+/// `sNeM` segment pre-selects a season and episode.
+pub fn parse_item_ref(value: &str) -> Result<(u64, Option<usize>, Option<usize>)> {
+    if let Ok(id) = value.parse::<u64>() {
+        return Ok((id, None, None));
+    }
+
+    let url =
+        Url::parse(value).map_err(|_| anyhow!("'{}' is neither an item id nor a URL", value))?;
+
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    let id = segments
+        .iter()
+        .find_map(|segment| segment.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("no item id found in '{}'", value))?;
+
+    let (season, episode) = segments
+        .last()
+        .map(|segment| parse_episode_suffix(segment))
+        .unwrap_or((None, None));
+
+    Ok((id, season, episode))
+}
+
+/// Decodes a trailing `s5e3`-style segment; anything that does not match the
+/// pattern selects nothing rather than erroring, since it may be a title slug.
+fn parse_episode_suffix(segment: &str) -> (Option<usize>, Option<usize>) {
+    let rest = match segment.strip_prefix('s') {
+        Some(rest) => rest,
+        None => return (None, None),
+    };
+
+    let (season, episode) = match rest.split_once('e') {
+        Some((season, episode)) => (season, Some(episode)),
+        None => (rest, None),
+    };
+
+    let season: Option<usize> = season.parse().ok();
+    if season.is_none() {
+        return (None, None);
+    }
+
+    match episode {
+        Some(episode) => match episode.parse().ok() {
+            Some(episode) => (season, Some(episode)),
+            None => (None, None),
+        },
+        None => (season, None),
+    }
+}
+
+/// Digest algorithms supported for post-download verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Md5,
+}
+
+/// Computes the file's digest as a lowercase hex string, streaming in fixed
+/// size blocks so memory stays bounded regardless of file size.
+pub fn file_digest(path: &std::path::Path, algorithm: HashAlgorithm) -> Result<String> {
+    use sha2::Digest;
+    use std::io::Read;
+
+    fn stream_into<D: Digest>(mut hasher: D, mut file: std::fs::File) -> Result<Vec<u8>> {
+        let mut buffer = [0u8; 64 * 1024];
+
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        Ok(hasher.finalize().to_vec())
+    }
+
+    let file = std::fs::File::open(path)?;
+
+    let digest = match algorithm {
+        HashAlgorithm::Sha256 => stream_into(sha2::Sha256::new(), file)?,
+        HashAlgorithm::Md5 => stream_into(md5::Md5::new(), file)?,
+    };
+
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Sends a request, logging method, redacted URL, status and elapsed time at
+/// trace verbosity. A thin wrapper around `execute` so both the API client
+/// and the downloader get request logging without a middleware dependency.
+pub async fn send_traced(
+    client: &reqwest::Client,
+    builder: reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let request = builder.build()?;
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let started = std::time::Instant::now();
+    let result = client.execute(request).await;
+
+    if log::log_enabled!(log::Level::Trace) {
+        let outcome = match &result {
+            Ok(response) => response.status().to_string(),
+            Err(err) => format!("error: {}", err),
+        };
+        log::trace!("{}", trace_line(&method, &url, &outcome, started.elapsed()));
+    }
+
+    result
+}
+
+/// One formatted trace line for an HTTP exchange. Credentials are redacted
+/// here so no caller can log them by accident.
+fn trace_line(
+    method: &reqwest::Method,
+    url: &str,
+    outcome: &str,
+    elapsed: std::time::Duration,
+) -> String {
+    format!("{} {} -> {} in {:?}", method, redact_url(url), outcome, elapsed)
+}
+
+/// Masks credential-carrying query parameters so trace logs are safe to
+/// share. Bearer tokens live in headers, which are never logged at all.
+pub fn redact_url(url: &str) -> String {
+    const SENSITIVE: [&str; 4] = ["access_token", "refresh_token", "token", "client_secret"];
+
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(_) => return url.to_owned(),
+    };
+
+    if parsed.query().is_some() {
+        let redacted: Vec<(String, String)> = parsed
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if SENSITIVE.contains(&name.as_ref()) {
+                    "***".to_owned()
+                } else {
+                    value.into_owned()
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+
+        parsed.query_pairs_mut().clear().extend_pairs(redacted);
+    }
+
+    parsed.to_string()
+}
+
+/// Most worker connections a download may open; more than this only
+/// antagonizes CDNs without going any faster.
+pub const MAX_THREADS: u64 = 64;
+
+/// Marker value for `--threads auto`, resolved to a real count once the
+/// payload size is known. Deliberately zero: no explicit count can collide
+/// with it, since the parser rejects zero.
+pub const AUTO_THREADS: u64 = 0;
+
+/// Bytes of payload each thread should be worth before another one is
+/// added; below this share an extra connection is pure overhead.
+const AUTO_BYTES_PER_THREAD: u64 = 16 * 1024 * 1024;
+
+/// Thread count for `--threads auto`: one thread per 16 MiB of payload, at
+/// least one, and never more than `max` (the machine-derived cap).
+pub fn auto_threads(total_size: u64, max: usize) -> u64 {
+    (total_size / AUTO_BYTES_PER_THREAD)
+        .max(1)
+        .min(max.max(1) as u64)
+}
+
+/// Parses the --threads flag: "auto" defers the choice to the download,
+/// zero is rejected outright, absurdly high values are clamped to
+/// [`MAX_THREADS`] with a warning.
+pub fn parse_thread_count(value: &str) -> Result<u64> {
+    if value.trim().eq_ignore_ascii_case("auto") {
+        return Ok(AUTO_THREADS);
+    }
+
+    let threads: u64 = value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid thread count '{}'", value))?;
+
+    if threads == 0 {
+        return Err(anyhow!("--threads must be at least 1"));
+    }
+
+    if threads > MAX_THREADS {
+        log::warn!(
+            "--threads {} is clamped to the maximum of {}",
+            threads,
+            MAX_THREADS
+        );
+        return Ok(MAX_THREADS);
+    }
+
+    Ok(threads)
+}
+
+/// Parses a human-readable byte amount like "500K", "16M" or "2G", used for
+/// both rate caps and chunk sizes.
+pub fn parse_byte_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+
+    let (digits, multiplier) = match value.chars().last() {
+        Some(suffix) if suffix.eq_ignore_ascii_case(&'k') => (&value[..value.len() - 1], 1024u64),
+        Some(suffix) if suffix.eq_ignore_ascii_case(&'m') => {
+            (&value[..value.len() - 1], 1024 * 1024)
+        }
+        Some(suffix) if suffix.eq_ignore_ascii_case(&'g') => {
+            (&value[..value.len() - 1], 1024 * 1024 * 1024)
+        }
+        _ => (value, 1),
+    };
+
+    let number: f64 = digits
+        .parse()
+        .map_err(|_| anyhow!("invalid size '{}', expected e.g. 500K or 16M", value))?;
+
+    if number <= 0.0 {
+        return Err(anyhow!("size must be positive, got '{}'", value));
+    }
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Values available to `--name-template` placeholders for one file.
+#[derive(Debug, Default)]
+pub struct FilenameContext {
+    pub title: String,
+    pub year: u16,
+    pub quality: String,
+    pub season: Option<usize>,
+    pub episode: Option<usize>,
+    pub episode_title: Option<String>,
+}
+
+/// Expands a filename template like `{title} ({year}) s{season:02}e{episode:02}`.
+/// Numeric placeholders accept a `:0N` zero-padding spec; placeholders the
+/// item cannot provide (e.g. `{season}` for a movie) are an error.
+pub fn render_template(tmpl: &str, ctx: &FilenameContext) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = tmpl.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => spec.push(c),
+                None => return Err(anyhow!("unclosed '{{' in template '{}'", tmpl)),
+            }
+        }
+
+        let (name, width) = match spec.split_once(':') {
+            Some((name, pad)) => {
+                let width = pad
+                    .strip_prefix('0')
+                    .and_then(|digits| digits.parse::<usize>().ok())
+                    .ok_or_else(|| {
+                        anyhow!("invalid padding '{}' in '{{{}}}'; use e.g. {{season:02}}", pad, spec)
+                    })?;
+                (name, Some(width))
+            }
+            None => (spec.as_str(), None),
+        };
+
+        let numeric = |value: Option<usize>| {
+            let value = value.ok_or_else(|| {
+                anyhow!("template placeholder '{{{}}}' is not available for this item", name)
+            })?;
+            Ok::<_, anyhow::Error>(format!("{:0width$}", value, width = width.unwrap_or(0)))
+        };
+
+        let text = |value: &str| {
+            if width.is_some() {
+                return Err(anyhow!(
+                    "padding is only supported for numeric placeholders, not '{{{}}}'",
+                    spec
+                ));
+            }
+            Ok(value.to_owned())
+        };
+
+        let rendered = match name {
+            "title" => text(&ctx.title)?,
+            "year" => numeric(Some(ctx.year as usize))?,
+            "quality" => text(&ctx.quality)?,
+            "season" => numeric(ctx.season)?,
+            "episode" => numeric(ctx.episode)?,
+            "episode_title" => {
+                let title = ctx.episode_title.as_deref().ok_or_else(|| {
+                    anyhow!("template placeholder '{{{}}}' is not available for this item", name)
+                })?;
+                text(title)?
+            }
+            other => {
+                return Err(anyhow!(
+                    "unknown template placeholder '{{{}}}'; known: {{title}}, {{year}}, \
+                     {{quality}}, {{season}}, {{episode}}, {{episode_title}}",
+                    other
+                ))
+            }
+        };
+
+        out.push_str(&rendered);
+    }
+
+    Ok(sanitize_filename(&out))
+}
+
+/// Keeps a rendered name usable as a single path component: path separators
+/// would silently create directories, so they become dashes.
+fn sanitize_filename(name: &str) -> String {
+    name.replace(['/', '\\'], "-").trim().to_owned()
+}
+
 pub struct Utils;
 
 impl Utils {
-    pub fn generate_filename(
-        item: &Item,
-        quality: &str,
-        season: Option<usize>,
-        episode: Option<usize>,
-    ) -> Result<String> {
+    /// Human-readable item title: "Russian / English" API titles become
+    /// "Russian (English)".
+    pub fn item_title(item: &Item) -> String {
         let info = match item {
             Item::Movie { info, .. } => info,
             Item::Series { info, .. } => info,
             Item::DocSeries { info, .. } => info,
             Item::TvShow { info, .. } => info,
+            Item::Other { info, .. } => info,
         };
 
-        let title = if info.title.contains('/') {
+        if info.title.contains('/') {
             let (rus_title, eng_title) = info.title.split_once('/').unwrap();
             format!("{} ({})", rus_title.trim(), eng_title.trim())
         } else {
             info.title.to_owned()
+        }
+    }
+
+    /// Release year straight off the item's general info.
+    pub fn item_year(item: &Item) -> u16 {
+        let info = match item {
+            Item::Movie { info, .. } => info,
+            Item::Series { info, .. } => info,
+            Item::DocSeries { info, .. } => info,
+            Item::TvShow { info, .. } => info,
+            Item::Other { info, .. } => info,
         };
 
-        match item {
-            Item::TvShow { seasons, .. }
-            | Item::Series { seasons, .. }
-            | Item::DocSeries { seasons, .. } => {
-                let season_number = season.unwrap_or(1);
-                let episode_number = episode.unwrap_or(1);
+        info.year
+    }
 
+    /// `episode` is the concrete (season, episode) pair the download loop is
+    /// fetching, not the CLI's optional selectors; a series without one (or a
+    /// movie with stray season/episode args) gets the plain movie-style name
+    /// instead of a fabricated s1e1. `part` is the 1-based video index of a
+    /// multi-part movie and only appears when one is given.
+    pub fn generate_filename(
+        item: &Item,
+        quality: &str,
+        episode: Option<(usize, usize)>,
+        part: Option<usize>,
+        extension: &str,
+    ) -> Result<String> {
+        let title = Self::item_title(item);
+
+        match (item, episode) {
+            (
+                Item::TvShow { seasons, .. }
+                | Item::Series { seasons, .. }
+                | Item::DocSeries { seasons, .. },
+                Some((season_number, episode_number)),
+            ) => {
                 let season = seasons
                     .iter()
                     .find(|s| s.number == season_number)
@@ -104,14 +461,319 @@ impl Utils {
                     width = episode_width
                 );
 
-                return Ok(format!(
-                    "{0} [{2}, {3}] [{1}].mp4",
-                    title, quality, season_title, episode_title
-                ));
+                Ok(format!(
+                    "{0} [{2}, {3}] [{1}].{4}",
+                    title, quality, season_title, episode_title, extension
+                ))
             }
-            _ => {}
+            _ => match part {
+                Some(part) => Ok(format!(
+                    "{0} [Part {1}] [{2}].{3}",
+                    title, part, quality, extension
+                )),
+                None => Ok(format!("{0} [{1}].{2}", title, quality, extension)),
+            },
+        }
+    }
+}
+
+/// Parses a "START-END" byte window like "0-100M", both halves in
+/// [`parse_byte_size`] notation.
+pub fn parse_byte_range(value: &str) -> Result<(u64, u64)> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| anyhow!("byte ranges look like START-END, e.g. 0-100M"))?;
+
+    // Zero is a fine start even though it is never a valid size.
+    let start = match start.trim() {
+        "0" => 0,
+        start => parse_byte_size(start)?,
+    };
+    let end = parse_byte_size(end.trim())?;
+
+    if start >= end {
+        bail!("byte range start must be below its end");
+    }
+
+    Ok((start, end))
+}
+
+/// Container extension named by the URL path itself, e.g.
+/// "http://host/movie.mkv?token=x" -> "mkv". `None` when the path does not
+/// end in a known container.
+pub fn extension_from_url(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    known_container(path.rsplit('.').next()?)
+}
+
+/// Container extension a HEAD response names, preferring an explicit
+/// `Content-Disposition` filename over the `Content-Type`.
+pub fn extension_from_headers(
+    content_type: Option<&str>,
+    content_disposition: Option<&str>,
+) -> Option<String> {
+    let from_disposition = content_disposition.and_then(|value| {
+        let filename = value.split("filename=").nth(1)?;
+        let filename = filename.split(';').next()?.trim().trim_matches('"');
+        known_container(filename.rsplit('.').next()?)
+    });
+
+    from_disposition.or_else(|| match content_type?.split(';').next()?.trim() {
+        "video/mp4" => Some("mp4".to_owned()),
+        "video/x-matroska" => Some("mkv".to_owned()),
+        "video/mp2t" => Some("ts".to_owned()),
+        "video/webm" => Some("webm".to_owned()),
+        "video/quicktime" => Some("mov".to_owned()),
+        "video/x-msvideo" => Some("avi".to_owned()),
+        _ => None,
+    })
+}
+
+fn known_container(candidate: &str) -> Option<String> {
+    let candidate = candidate.to_ascii_lowercase();
+
+    matches!(
+        candidate.as_str(),
+        "mp4" | "mkv" | "ts" | "avi" | "webm" | "m4v" | "mov"
+    )
+    .then_some(candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        extension_from_headers, extension_from_url, file_digest, parse_byte_range,
+        parse_byte_size, parse_item_ref, parse_thread_count, render_template, FilenameContext,
+        HashAlgorithm, MAX_THREADS,
+    };
+
+    fn episode_ctx() -> FilenameContext {
+        FilenameContext {
+            title: "The Show".to_string(),
+            year: 2020,
+            quality: "1080p".to_string(),
+            season: Some(2),
+            episode: Some(7),
+            episode_title: Some("Pilot / Redux".to_string()),
+        }
+    }
+
+    #[test]
+    fn templates_expand_placeholders_with_padding() {
+        let ctx = episode_ctx();
+
+        assert_eq!(
+            render_template("{title} ({year}) s{season:02}e{episode:02} [{quality}]", &ctx)
+                .unwrap(),
+            "The Show (2020) s02e07 [1080p]"
+        );
+        assert_eq!(
+            render_template("{season:03}x{episode}", &ctx).unwrap(),
+            "002x7"
+        );
+    }
+
+    #[test]
+    fn templates_sanitize_path_separators() {
+        let ctx = episode_ctx();
+
+        assert_eq!(
+            render_template("{episode_title}", &ctx).unwrap(),
+            "Pilot - Redux"
+        );
+    }
+
+    #[test]
+    fn movie_templates_reject_episode_placeholders() {
+        let ctx = FilenameContext {
+            title: "The Movie".to_string(),
+            year: 2021,
+            quality: "720p".to_string(),
+            ..FilenameContext::default()
+        };
+
+        assert_eq!(
+            render_template("{title} ({year})", &ctx).unwrap(),
+            "The Movie (2021)"
+        );
+
+        let err = render_template("{title} s{season:02}", &ctx).unwrap_err();
+        assert!(err.to_string().contains("{season}"));
+    }
+
+    #[test]
+    fn templates_reject_unknown_placeholders_and_bad_syntax() {
+        let ctx = episode_ctx();
+
+        let err = render_template("{titel}", &ctx).unwrap_err();
+        assert!(err.to_string().contains("unknown template placeholder"));
+        assert!(err.to_string().contains("{title}"));
+
+        assert!(render_template("{title", &ctx).is_err());
+        assert!(render_template("{title:02}", &ctx).is_err());
+        assert!(render_template("{season:2}", &ctx).is_err());
+    }
+
+    #[test]
+    fn item_refs_accept_bare_ids_and_urls() {
+        assert_eq!(parse_item_ref("12345").unwrap(), (12345, None, None));
+        assert_eq!(
+            parse_item_ref("https://kino.pub/item/view/12345").unwrap(),
+            (12345, None, None)
+        );
+        assert_eq!(
+            parse_item_ref("https://kino.pub/item/view/12345/s5e3").unwrap(),
+            (12345, Some(5), Some(3))
+        );
+        assert_eq!(
+            parse_item_ref("https://kino.pub/item/view/12345/s5").unwrap(),
+            (12345, Some(5), None)
+        );
+        // A title slug after the id is not an episode reference.
+        assert_eq!(
+            parse_item_ref("https://kino.pub/item/view/12345/some-title").unwrap(),
+            (12345, None, None)
+        );
+    }
+
+    #[test]
+    fn item_refs_reject_garbage() {
+        assert!(parse_item_ref("not-a-url").is_err());
+        assert!(parse_item_ref("https://kino.pub/item/view/").is_err());
+    }
+
+    #[test]
+    fn digests_match_known_vectors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        std::fs::write(&path, b"abc").unwrap();
+
+        assert_eq!(
+            file_digest(&path, HashAlgorithm::Sha256).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            file_digest(&path, HashAlgorithm::Md5).unwrap(),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+    }
+
+    #[test]
+    fn parses_plain_and_suffixed_sizes() {
+        assert_eq!(parse_byte_size("1000").unwrap(), 1000);
+        assert_eq!(parse_byte_size("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_byte_size("16M").unwrap(), 16 * 1024 * 1024);
+        assert_eq!(parse_byte_size("1.5m").unwrap(), 1_572_864);
+        assert_eq!(parse_byte_size("1g").unwrap(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_byte_size("fast").is_err());
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("-2M").is_err());
+    }
+
+    #[test]
+    fn parses_byte_windows() {
+        assert_eq!(parse_byte_range("0-100M").unwrap(), (0, 100 * 1024 * 1024));
+        assert_eq!(parse_byte_range("1000 - 2000").unwrap(), (1000, 2000));
+
+        assert!(parse_byte_range("100").is_err());
+        assert!(parse_byte_range("2000-1000").is_err());
+        assert!(parse_byte_range("500-500").is_err());
+    }
+
+    #[test]
+    fn trace_lines_never_contain_the_token() {
+        let line = super::trace_line(
+            &reqwest::Method::GET,
+            "https://api.example.com/v1/user?access_token=sekret123&page=2",
+            "200 OK",
+            std::time::Duration::from_millis(12),
+        );
+
+        assert!(!line.contains("sekret123"), "got: {}", line);
+        assert!(line.contains("access_token=***"));
+        assert!(line.contains("page=2"));
+        assert!(line.starts_with("GET https://api.example.com/v1/user?"));
+        assert!(line.contains("200 OK"));
+    }
+
+    #[test]
+    fn thread_counts_reject_zero_and_clamp_high_values() {
+        assert!(parse_thread_count("0").is_err());
+        assert!(parse_thread_count("four").is_err());
+        assert_eq!(parse_thread_count("4").unwrap(), 4);
+        assert_eq!(parse_thread_count("500").unwrap(), MAX_THREADS);
+        assert_eq!(parse_thread_count("auto").unwrap(), super::AUTO_THREADS);
+        assert_eq!(parse_thread_count(" AUTO ").unwrap(), super::AUTO_THREADS);
+    }
+
+    #[test]
+    fn auto_threads_scales_with_size_up_to_the_cap() {
+        use super::auto_threads;
+
+        const MIB: u64 = 1024 * 1024;
+
+        // Small files are not worth the connection overhead.
+        assert_eq!(auto_threads(0, 8), 1);
+        assert_eq!(auto_threads(16 * MIB - 1, 8), 1);
+
+        // One more thread per 16 MiB from there on.
+        assert_eq!(auto_threads(32 * MIB, 8), 2);
+        assert_eq!(auto_threads(64 * MIB, 8), 4);
+        assert_eq!(auto_threads(128 * MIB, 8), 8);
+
+        // The machine-derived cap wins for huge files.
+        assert_eq!(auto_threads(10 * 1024 * MIB, 8), 8);
+        assert_eq!(auto_threads(u64::MAX, 8), 8);
+
+        // A degenerate cap still downloads with one thread.
+        assert_eq!(auto_threads(64 * MIB, 0), 1);
+    }
+
+    #[test]
+    fn infers_the_extension_from_url_shapes() {
+        let cases = [
+            ("http://cdn.example.com/path/movie.mkv", Some("mkv")),
+            ("http://cdn.example.com/movie.MP4?token=abc", Some("mp4")),
+            ("http://cdn.example.com/stream.ts#fragment", Some("ts")),
+            // No extension in the path; the host's dots must not count.
+            ("http://cdn.example.com/stream/810023", None),
+            // Unknown containers are left for the headers to decide.
+            ("http://cdn.example.com/archive.tar.gz", None),
+        ];
+
+        for (url, expected) in cases {
+            assert_eq!(extension_from_url(url).as_deref(), expected, "{}", url);
         }
+    }
+
+    #[test]
+    fn infers_the_extension_from_head_response_headers() {
+        assert_eq!(
+            extension_from_headers(Some("video/x-matroska"), None).as_deref(),
+            Some("mkv")
+        );
+        assert_eq!(
+            extension_from_headers(Some("video/mp4; charset=binary"), None).as_deref(),
+            Some("mp4")
+        );
+
+        // An explicit filename beats a generic content type.
+        assert_eq!(
+            extension_from_headers(
+                Some("application/octet-stream"),
+                Some("attachment; filename=\"episode.webm\"; size=100")
+            )
+            .as_deref(),
+            Some("webm")
+        );
 
-        Ok(format!("{0} [{1}].mp4", title, quality))
+        assert_eq!(
+            extension_from_headers(Some("application/octet-stream"), None),
+            None
+        );
     }
 }